@@ -1,224 +1,672 @@
 mod watwriter;
+pub mod runtime;
 
-use crate::codegen::watwriter::{Function, FunctionType, Global, Module, Statement, ValueType};
-use crate::syntax::ast::{BinaryOperator, BlockDecl, Expr, Identifier, LiteralExpr, Program, UnaryOperator};
+use std::collections::HashMap;
+use std::fmt;
+use crate::codegen::runtime::Trap;
+use crate::codegen::watwriter::{Conversion, Function, FunctionType, Global, Import, Instruction, Module, NumericOp, ValueType, IMPORTS};
+use crate::syntax::Span;
+use crate::syntax::ast::{BinaryOperator, BlockDecl, Expr, ExprKind, Identifier, LiteralExpr, Param, Program, Type, UnaryOperator};
 
+pub use crate::codegen::watwriter::{function_export, global_export, HOST_MODULE, RUNTIME_MODULE};
+
+/// Generates the text format of a module from a type checked program, for
+/// debugging.
 pub fn generate_assembly(program: Program) -> String {
+    generate_module(program).to_wat()
+}
+
+/// Generates a binary module from a type checked program.
+pub fn generate_wasm(program: Program) -> Vec<u8> {
+    generate_module(program).into_wasm()
+}
+
+/// The signature of every function in a type checked program, the ones the
+/// host provides included.
+pub fn signatures(program: &Program) -> HashMap<Identifier, Signature> {
+    let mut compiler = Compiler::new();
+    compiler.declare_functions(program);
+    compiler.functions
+}
+
+/// Whether the runtime has a function named `name`, which the functions of
+/// a program can't be named.
+pub fn is_runtime_function(name: &str) -> bool {
+    IMPORTS.iter().any(|import| import.name == name)
+}
+
+/// The functions a type checked program expects the host to provide.
+pub fn externs(program: &Program) -> Vec<(Identifier, Signature)> {
+    let mut compiler = Compiler::new();
+    compiler.declare_functions(program);
+    compiler.externs.into_iter()
+        .map(|ident| {
+            let signature = compiler.functions[&ident].clone();
+            (ident, signature)
+        })
+        .collect()
+}
+
+fn generate_module(program: Program) -> Module {
     let mut compiler = Compiler::new();
+    compiler.declare_functions(&program);
 
     for expr in program {
-        generate_expr(&mut compiler, expr);
+        generate_statement(&mut compiler, expr);
+    }
+
+    // A user defined main runs after the top level statements.
+    if let Some(main) = compiler.functions.get("main") {
+        let returns_value = value_type(main.ret).is_some();
+        compiler.current.add_instruction(Instruction::Call("main".to_string()));
+        if returns_value {
+            compiler.current.add_instruction(Instruction::Drop);
+        }
     }
 
-    // Add main function.
+    // Add script function.
     compiler.module.add_function(compiler.current.clone());
 
-    compiler.to_wat()
+    runtime::add_runtime(&mut compiler.module);
+
+    compiler.module
+}
+
+/// The wasm type a value of `ty` is represented by, `None` for unit.
+fn value_type(ty: Type) -> Option<ValueType> {
+    match ty {
+        Type::I64 => Some(ValueType::I64),
+        Type::F32 => Some(ValueType::F32),
+        Type::F64 => Some(ValueType::F64),
+        Type::Unit => None,
+        _ => Some(ValueType::I32),
+    }
+}
+
+/// Generates an expression whose value isn't used.
+fn generate_statement(compiler: &mut Compiler, expr: Expr) {
+    let returns_value = value_type(expr.ty).is_some();
+    generate_expr(compiler, expr);
+
+    if returns_value {
+        compiler.current.add_instruction(Instruction::Drop);
+    }
 }
 
 fn generate_expr(compiler: &mut Compiler, expr: Expr) {
-    match expr {
-        Expr::Block(expressions) => generate_block(compiler, expressions),
-        Expr::Binary { left, op, right } => generate_binary(compiler, left, op, right),
-        Expr::Unary { op, expr } => generate_unary(compiler, op, expr),
-        Expr::LetAssign { ident, initializer } => generate_let_assign(compiler, ident, initializer),
-        Expr::LetGet { ident } => generate_let_get(compiler, ident),
-        Expr::LetSet { ident, expr } => generate_let_set(compiler, ident, expr),
-        Expr::Print { value } => generate_print(compiler, value),
-        Expr::IfElse { condition, then, else_ } => generate_if_else(compiler, condition, then, else_),
-        Expr::Fun { ident, params, body, } => generate_fun(compiler, ident, params, body),
-        Expr::Call { callee, args } => generate_call(compiler, callee, args),
-        Expr::Literal(l) => generate_literal(compiler, l),
+    let ty = expr.ty;
+    let span = expr.span;
+    match expr.kind {
+        ExprKind::Block(expressions) => generate_block(compiler, expressions),
+        ExprKind::Binary { left, op, right } => generate_binary(compiler, left, op, right, ty, span),
+        ExprKind::Unary { op, expr } => generate_unary(compiler, op, expr),
+        ExprKind::LetAssign { ident, ty, initializer } => generate_let_assign(compiler, ident, ty, *initializer),
+        ExprKind::LetGet { ident } => generate_let_get(compiler, ident),
+        ExprKind::LetSet { ident, expr } => generate_let_set(compiler, ident, expr),
+        ExprKind::Print { value } => generate_print(compiler, value),
+        ExprKind::IfElse { condition, then, else_ } => generate_if_else(compiler, condition, then, else_),
+        ExprKind::Fun { ident, params, ret, body } => generate_fun(compiler, ident, params, ret, body),
+        ExprKind::Extern { ident, params, ret } => generate_extern(compiler, ident, params, ret),
+        ExprKind::Call { callee, args } => generate_call(compiler, callee, args),
+        ExprKind::Return { value } => generate_return(compiler, value),
+        ExprKind::While { condition, body } => generate_while(compiler, condition, body),
+        ExprKind::For { ident, start, end, body } => generate_for(compiler, ident, *start, *end, body),
+        ExprKind::Break => generate_branch(compiler, "break"),
+        ExprKind::Continue => generate_branch(compiler, "continue"),
+        ExprKind::Interpolation(parts) => generate_interpolation(compiler, parts),
+        ExprKind::Literal(l) => generate_literal(compiler, l, ty),
     }
 }
 
+/// Generates `expr` and converts its value to `to`.
+fn generate_coerced(compiler: &mut Compiler, expr: Expr, to: Type) {
+    let from = expr.ty;
+    generate_expr(compiler, expr);
+    generate_conversion(compiler, from, to);
+}
+
+fn generate_conversion(compiler: &mut Compiler, from: Type, to: Type) {
+    let conversion = match (from, to) {
+        (Type::I32, Type::I64) => Conversion::I64ExtendI32S,
+        (Type::I32, Type::F32) => Conversion::F32ConvertI32S,
+        (Type::I32, Type::F64) => Conversion::F64ConvertI32S,
+        (Type::I64, Type::F64) => Conversion::F64ConvertI64S,
+        (Type::F32, Type::F64) => Conversion::F64PromoteF32,
+        _ => return,
+    };
+
+    compiler.current.add_instruction(Instruction::Convert(conversion));
+}
+
 fn generate_block(compiler: &mut Compiler, expressions: Vec<Expr>) {
     for e in expressions {
-        generate_expr(compiler, e);
+        generate_statement(compiler, e);
     }
 }
 
-fn generate_let_assign(compiler: &mut Compiler, ident: Identifier, initializer: Box<Expr>) {
-    let global = Global {
-        name: ident.clone(),
-        mutable: true,
-        value_type: ValueType::F64,
-    };
-
-    compiler.module.add_global(global);
+fn generate_let_assign(compiler: &mut Compiler, ident: Identifier, ty: Type, initializer: Expr) {
+    declare_variable(compiler, &ident, ty);
 
     // Generate initializer.
-    generate_expr(compiler, *initializer);
+    generate_coerced(compiler, initializer, ty);
+    set_variable(compiler, &ident);
+}
+
+/// Declares a global in the script and a local in functions.
+fn declare_variable(compiler: &mut Compiler, ident: &Identifier, ty: Type) {
+    let value_type = value_type(ty).unwrap();
+    compiler.types.insert(ident.clone(), ty);
 
     if compiler.current.function_type == FunctionType::Script {
         // Global var.
-        let s = Statement::String(format!("global.set ${}", ident.clone()));
-        compiler.current.add_statement(s);
-    } else {
+        if !compiler.module.globals.iter().any(|g| &g.name == ident) {
+            let global = Global {
+                name: ident.clone(),
+                mutable: true,
+                value_type,
+            };
+            compiler.module.add_global(global);
+        }
+    } else if !compiler.is_local(ident) {
         // Local var.
-        compiler.current.add_local(ident.clone());
-
-        let s = Statement::String(format!("local.set ${}", ident.clone()));
-        compiler.current.add_statement(s);
-    };
+        compiler.current.add_local(ident.clone(), value_type);
+    }
 }
 
-fn generate_let_get(compiler: &mut Compiler, ident: Identifier) {
-    let s = if compiler.is_local(&ident) {
+fn set_variable(compiler: &mut Compiler, ident: &Identifier) {
+    let instruction = if compiler.is_local(ident) {
         // Local var.
-        Statement::String(format!("local.get ${}", ident.clone()))
+        Instruction::LocalSet(ident.clone())
     } else {
         // Global var.
-        Statement::String(format!("global.get ${}", ident.clone()))
+        Instruction::GlobalSet(ident.clone())
     };
 
-    compiler.current.add_statement(s);
+    compiler.current.add_instruction(instruction);
 }
 
-fn generate_let_set(compiler: &mut Compiler, ident: Identifier, expr: Box<Expr>) {
-    generate_expr(compiler, *expr);
-
-    let s = if compiler.is_local(&ident) {
+fn generate_let_get(compiler: &mut Compiler, ident: Identifier) {
+    let instruction = if compiler.is_local(&ident) {
         // Local var.
-        Statement::String(format!("local.set ${}", ident.clone()))
+        Instruction::LocalGet(ident)
     } else {
         // Global var.
-        Statement::String(format!("global.set ${}", ident.clone()))
+        Instruction::GlobalGet(ident)
     };
 
-    compiler.current.add_statement(s);
+    compiler.current.add_instruction(instruction);
+}
+
+fn generate_let_set(compiler: &mut Compiler, ident: Identifier, expr: Box<Expr>) {
+    let to = compiler.types[&ident];
+    generate_coerced(compiler, *expr, to);
+    set_variable(compiler, &ident);
 }
 
 fn generate_print(compiler: &mut Compiler, value: Box<Expr>) {
+    let ty = value.ty;
     generate_expr(compiler, *value);
 
-    let s = Statement::String("call $log".to_string());
-
-    compiler.current.add_statement(s);
+    match ty {
+        Type::I32 | Type::Bool => compiler.current.add_instruction(Instruction::Call("log".to_string())),
+        Type::I64 => compiler.current.add_instruction(Instruction::Call("log_i64".to_string())),
+        Type::F64 => compiler.current.add_instruction(Instruction::Call("log_f64".to_string())),
+        _ => {
+            generate_to_str(compiler, ty);
+            compiler.current.add_instruction(Instruction::Call(runtime::PRINT_STR.to_string()));
+        }
+    }
 }
 
 fn generate_if_else(compiler: &mut Compiler, condition: Box<Expr>, then: Box<Expr>, else_: Option<Box<Expr>>) {
     generate_expr(compiler, *condition);
 
-    let if_ = r#"(if (then"#;
+    let then = compiler.nested(|c| generate_statement(c, *then));
+    let else_ = match else_ {
+        Some(else_) => compiler.nested(|c| generate_statement(c, *else_)),
+        None => vec![],
+    };
+
+    compiler.current.add_instruction(Instruction::If { result: None, then, else_ });
+}
+
+// Loops are wrapped in a block, `break` branches to the end of the block and
+// `continue` to the start of the next iteration.
+//
+//   (block $__break_N
+//     (loop $__loop_N
+//       condition
+//       br_if $__break_N
+//       (block $__continue_N
+//         body)
+//       br $__loop_N))
+
+fn generate_while(compiler: &mut Compiler, condition: Box<Expr>, body: Box<Expr>) {
+    let id = compiler.begin_loop();
+
+    let loop_ = compiler.nested(|c| {
+        generate_expr(c, *condition);
+        c.current.add_instruction(Instruction::Numeric(ValueType::I32, NumericOp::Eqz));
+        c.current.add_instruction(Instruction::BrIf(format!("__break_{}", id)));
+
+        generate_loop_body(c, id, *body);
+    });
+
+    compiler.end_loop(id, loop_);
+}
+
+fn generate_for(compiler: &mut Compiler, ident: Identifier, start: Expr, end: Expr, body: Box<Expr>) {
+    // The end is evaluated once, before the first iteration.
+    let end_ident = format!("__end_{}", compiler.labels);
+    declare_variable(compiler, &ident, Type::I32);
+    declare_variable(compiler, &end_ident, Type::I32);
+
+    generate_expr(compiler, start);
+    set_variable(compiler, &ident);
+    generate_expr(compiler, end);
+    set_variable(compiler, &end_ident);
+
+    let id = compiler.begin_loop();
+
+    let loop_ = compiler.nested(|c| {
+        generate_let_get(c, ident.clone());
+        generate_let_get(c, end_ident);
+        c.current.add_instruction(Instruction::Numeric(ValueType::I32, NumericOp::GeS));
+        c.current.add_instruction(Instruction::BrIf(format!("__break_{}", id)));
+
+        generate_loop_body(c, id, *body);
+
+        // Count up after the body, also when it continues.
+        generate_let_get(c, ident.clone());
+        c.current.add_instruction(Instruction::I32Const(1));
+        c.current.add_instruction(Instruction::Numeric(ValueType::I32, NumericOp::Add));
+        set_variable(c, &ident);
+    });
+
+    compiler.end_loop(id, loop_);
+}
+
+fn generate_loop_body(compiler: &mut Compiler, id: usize, body: Expr) {
+    let body = compiler.nested(|c| generate_statement(c, body));
+    compiler.current.add_instruction(Instruction::Block(Some(format!("__continue_{}", id)), body));
+}
+
+fn generate_branch(compiler: &mut Compiler, label: &str) {
+    let id = *compiler.loops.last().unwrap();
+    compiler.current.add_instruction(Instruction::Br(format!("__{}_{}", label, id)));
+}
+
+fn generate_binary(compiler: &mut Compiler, left: Box<Expr>, op: BinaryOperator, right: Box<Expr>, ty: Type, span: Span) {
+    if matches!(op, BinaryOperator::And | BinaryOperator::Or) {
+        return generate_logical(compiler, *left, op, *right);
+    }
 
-    compiler.current.add_statement(Statement::String(if_.to_string()));
+    // Both operands are converted to the wider of their types.
+    let operand = match op {
+        BinaryOperator::Add
+        | BinaryOperator::Subtract
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Remainder => ty,
+        _ => left.ty.wider(right.ty),
+    };
 
-    // Generate then.
-    generate_expr(compiler, *then);
+    generate_coerced(compiler, *left, operand);
+    generate_coerced(compiler, *right, operand);
 
-    let then_ = r#") (else"#;
-    compiler.current.add_statement(Statement::String(then_.to_string()));
+    if operand.is_float() {
+        generate_float_binary_op(compiler, op, operand);
+    } else {
+        if matches!(op, BinaryOperator::Divide | BinaryOperator::Remainder) {
+            generate_divisor_check(compiler, operand, span);
+        }
+        generate_binary_op(compiler, op, operand);
+    }
+}
 
-    // Generate else.
-    if else_.is_some() {
-        generate_expr(compiler, *else_.unwrap());
+/// Reports a division by zero as a Teal runtime error rather than letting
+/// the wasm trap, the divisor is on top of the stack.
+fn generate_divisor_check(compiler: &mut Compiler, operand: Type, span: Span) {
+    let value_type = value_type(operand).unwrap();
+    let divisor = format!("__divisor_{}", value_type.to_wat());
+    if !compiler.is_local(&divisor) {
+        compiler.current.add_local(divisor.clone(), value_type);
     }
 
-    compiler.current.add_statement(Statement::String("))".to_string()));
+    compiler.current.add_instruction(Instruction::LocalTee(divisor.clone()));
+    compiler.current.add_instruction(Instruction::Numeric(value_type, NumericOp::Eqz));
+    compiler.current.add_instruction(Instruction::If {
+        result: None,
+        then: vec![
+            Instruction::I32Const(Trap::DivisionByZero.code()),
+            Instruction::I32Const(span.start as i32),
+            Instruction::I32Const(span.end as i32),
+            Instruction::I32Const(span.line as i32),
+            Instruction::I32Const(span.column as i32),
+            Instruction::Call("trap".to_string()),
+        ],
+        else_: vec![],
+    });
+    compiler.current.add_instruction(Instruction::LocalGet(divisor));
+}
+
+/// `and` and `or` only evaluate the right operand when the left one doesn't
+/// decide the result already.
+fn generate_logical(compiler: &mut Compiler, left: Expr, op: BinaryOperator, right: Expr) {
+    generate_expr(compiler, left);
+
+    let right = compiler.nested(|c| generate_expr(c, right));
+    let (then, else_) = if op == BinaryOperator::And {
+        (right, vec![Instruction::I32Const(0)])
+    } else {
+        (vec![Instruction::I32Const(1)], right)
+    };
+
+    compiler.current.add_instruction(Instruction::If { result: Some(ValueType::I32), then, else_ });
 }
 
-fn generate_binary(compiler: &mut Compiler, left: Box<Expr>, op: BinaryOperator, right: Box<Expr>) {
-    generate_expr(compiler, *left);
-    generate_expr(compiler, *right);
+fn generate_float_binary_op(compiler: &mut Compiler, op: BinaryOperator, operand: Type) {
+    let operator = match op {
+        BinaryOperator::Subtract => NumericOp::Sub,
+        BinaryOperator::Add => NumericOp::Add,
+        BinaryOperator::Divide => NumericOp::Div,
+        BinaryOperator::Multiply => NumericOp::Mul,
+        BinaryOperator::Equal => NumericOp::Eq,
+        BinaryOperator::BangEqual => NumericOp::Ne,
+        BinaryOperator::GreaterThan => NumericOp::Gt,
+        BinaryOperator::GreaterThanEqual => NumericOp::Ge,
+        BinaryOperator::LessThan => NumericOp::Lt,
+        BinaryOperator::LessThanEqual => NumericOp::Le,
+        BinaryOperator::Remainder => unreachable!("the checker only allows integer remainders"),
+        BinaryOperator::And | BinaryOperator::Or => unreachable!("generated by generate_logical"),
+    };
 
-    generate_binary_op(compiler, op);
+    let value_type = value_type(operand).unwrap();
+    compiler.current.add_instruction(Instruction::Numeric(value_type, operator));
 }
 
-fn generate_binary_op(compiler: &mut Compiler, op: BinaryOperator) {
+fn generate_binary_op(compiler: &mut Compiler, op: BinaryOperator, operand: Type) {
     let operator = match op {
-        BinaryOperator::Subtract => "i32.sub",
-        BinaryOperator::Add => "i32.add",
-        BinaryOperator::Divide => "i32.div_u",
-        BinaryOperator::Multiply => "i32.mul",
-        BinaryOperator::Equal => "i32.eq",
-        BinaryOperator::BangEqual => "i32.ne",
-        BinaryOperator::GreaterThan => "i32.gt_u",
-        BinaryOperator::GreaterThanEqual => "i32.ge_u",
-        BinaryOperator::LessThan => "i32.lt_u",
-        BinaryOperator::LessThanEqual => "i32.le_u"
-    }.to_string();
+        BinaryOperator::Subtract => NumericOp::Sub,
+        BinaryOperator::Add => NumericOp::Add,
+        BinaryOperator::Divide => NumericOp::DivS,
+        BinaryOperator::Multiply => NumericOp::Mul,
+        BinaryOperator::Remainder => NumericOp::RemS,
+        BinaryOperator::Equal => NumericOp::Eq,
+        BinaryOperator::BangEqual => NumericOp::Ne,
+        BinaryOperator::GreaterThan => NumericOp::GtS,
+        BinaryOperator::GreaterThanEqual => NumericOp::GeS,
+        BinaryOperator::LessThan => NumericOp::LtS,
+        BinaryOperator::LessThanEqual => NumericOp::LeS,
+        BinaryOperator::And | BinaryOperator::Or => unreachable!("generated by generate_logical"),
+    };
 
-    compiler.current.add_statement(Statement::String(operator));
+    let value_type = value_type(operand).unwrap();
+    compiler.current.add_instruction(Instruction::Numeric(value_type, operator));
 }
 
 fn generate_unary(compiler: &mut Compiler, op: UnaryOperator, expr: Box<Expr>) {
-    generate_expr(compiler, *expr);
+    let ty = expr.ty;
+    let value_type = value_type(ty).unwrap();
 
     match op {
+        UnaryOperator::Negate if ty.is_float() => {
+            generate_expr(compiler, *expr);
+            compiler.current.add_instruction(Instruction::Numeric(value_type, NumericOp::Neg));
+        },
         UnaryOperator::Negate => {
-            compiler.current.add_statement(Statement::String("i32.neg".to_string()));
+            // Integers have no negate instruction, subtract from zero instead.
+            let zero = match value_type {
+                ValueType::I64 => Instruction::I64Const(0),
+                _ => Instruction::I32Const(0),
+            };
+            compiler.current.add_instruction(zero);
+            generate_expr(compiler, *expr);
+            compiler.current.add_instruction(Instruction::Numeric(value_type, NumericOp::Sub));
         },
-        UnaryOperator::Not => todo!()
+        UnaryOperator::Not => {
+            generate_expr(compiler, *expr);
+            compiler.current.add_instruction(Instruction::Numeric(ValueType::I32, NumericOp::Eqz));
+        }
     }
 }
 
-fn generate_fun(compiler: &mut Compiler, ident: Identifier, params: Vec<Identifier>, body: BlockDecl) {
+fn generate_fun(compiler: &mut Compiler, ident: Identifier, params: Vec<Param>, ret: Type, mut body: BlockDecl) {
     let main_clone = compiler.current.clone();
+    let types_clone = compiler.types.clone();
+    let ret_clone = std::mem::replace(&mut compiler.ret, ret);
+
+    let typed_params = params.iter()
+        .map(|p| (p.ident.clone(), value_type(p.ty).unwrap()))
+        .collect();
+    for p in params {
+        compiler.types.insert(p.ident, p.ty);
+    }
 
-    let f = Function::new(ident, params, Some(ValueType::I32), vec![], FunctionType::Function);
+    let f = Function::new(ident.clone(), typed_params, value_type(ret), vec![], FunctionType::Function);
     compiler.current = f;
 
+    // The value of a trailing expression is the result.
+    let returns_value = value_type(ret).is_some();
+    let last = match body.last() {
+        Some(last) if returns_value && last.is_value() => body.pop(),
+        _ => None,
+    };
+
     // Compile function expressions.
     for expr in body {
-        generate_expr(compiler, expr);
+        generate_statement(compiler, expr);
+    }
+
+    if let Some(last) = last {
+        generate_coerced(compiler, last, ret);
+    } else if returns_value {
+        // Every path returned already, the checker made sure of it.
+        compiler.current.add_instruction(Instruction::Unreachable);
     }
 
     compiler.module.add_function(compiler.current.clone());
+    compiler.module.export_function(ident);
 
     compiler.current = main_clone;
+    compiler.types = types_clone;
+    compiler.ret = ret_clone;
+}
+
+/// Imports a function from the host. It's exported again so the host can
+/// call every function of the program the same way.
+fn generate_extern(compiler: &mut Compiler, ident: Identifier, params: Vec<Param>, ret: Type) {
+    let params = params.iter().map(|p| value_type(p.ty).unwrap()).collect();
+    compiler.module.add_import(Import::new(ident.clone(), params, value_type(ret)));
+    compiler.module.export_function(ident);
+}
+
+fn generate_return(compiler: &mut Compiler, value: Option<Box<Expr>>) {
+    if let Some(value) = value {
+        let ret = compiler.ret;
+        generate_coerced(compiler, *value, ret);
+    }
+
+    compiler.current.add_instruction(Instruction::Return);
 }
 
 fn generate_call(compiler: &mut Compiler, callee: Box<Expr>, args: Vec<Expr>) {
+    let fun_name = match callee.kind {
+        ExprKind::LetGet { ident } => ident,
+        _ => todo!()
+    };
+
     // Generate args.
-    for a in args {
-        generate_expr(compiler, a);
+    let params = compiler.functions[&fun_name].params.clone();
+    for (a, param) in args.into_iter().zip(params) {
+        generate_coerced(compiler, a, param);
     }
 
-    let fun_name = match *callee {
-        Expr::LetGet { ident } => ident,
-        _ => todo!()
+    compiler.current.add_instruction(Instruction::Call(fun_name));
+}
+
+fn generate_interpolation(compiler: &mut Compiler, parts: Vec<Expr>) {
+    for (i, part) in parts.into_iter().enumerate() {
+        let ty = part.ty;
+        generate_expr(compiler, part);
+        generate_to_str(compiler, ty);
+
+        // Append to the parts before.
+        if i > 0 {
+            compiler.current.add_instruction(Instruction::Call(runtime::CONCAT.to_string()));
+        }
+    }
+}
+
+/// Converts the value of type `ty` on the stack to a string.
+fn generate_to_str(compiler: &mut Compiler, ty: Type) {
+    let fun_name = match ty {
+        Type::String => return,
+        Type::I64 => "i64_to_str",
+        Type::F32 => "f32_to_str",
+        Type::F64 => "f64_to_str",
+        _ => runtime::I32_TO_STR,
     };
 
-    let call = Statement::Call(fun_name);
-    compiler.current.add_statement(call);
+    compiler.current.add_instruction(Instruction::Call(fun_name.to_string()));
 }
 
-fn generate_literal(compiler: &mut Compiler, l: LiteralExpr) {
-    let statement = match l {
-        LiteralExpr::Number(n) => Statement::Const(n),
-        LiteralExpr::String(str) => Statement::String("i32.const 0".to_string()),
-        LiteralExpr::True => Statement::Const(1),
-        LiteralExpr::False => Statement::Const(0),
+/// Number literals are generated as the type the checker gave them.
+fn generate_literal(compiler: &mut Compiler, l: LiteralExpr, ty: Type) {
+    let instruction = match l {
+        LiteralExpr::Number(n) => match ty {
+            Type::I64 => Instruction::I64Const(n),
+            Type::F32 => Instruction::F32Const(n as f32),
+            Type::F64 => Instruction::F64Const(n as f64),
+            _ => Instruction::I32Const(n as i32),
+        },
+        LiteralExpr::Float(n) if ty == Type::F32 => Instruction::F32Const(n as f32),
+        LiteralExpr::Float(n) => Instruction::F64Const(n),
+        LiteralExpr::String(str) => Instruction::I32Const(compiler.module.add_data(str) as i32),
+        LiteralExpr::True => Instruction::I32Const(1),
+        LiteralExpr::False => Instruction::I32Const(0),
     };
 
-    compiler.current.add_statement(statement);
+    compiler.current.add_instruction(instruction);
+}
+
+/// The types a function takes and returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+impl Signature {
+    pub fn new(params: Vec<Type>, ret: Type) -> Self {
+        Signature { params, ret }
+    }
+}
+
+/// Formats the signature like a function type, e.g. `(i32, string) -> bool`.
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(Type::to_string).collect();
+        write!(f, "({}) -> {}", params.join(", "), self.ret)
+    }
 }
 
 #[derive(Clone)]
 struct Compiler {
     module: Module,
     current: Function,
+    /// Types of the variables in scope.
+    types: HashMap<Identifier, Type>,
+    functions: HashMap<Identifier, Signature>,
+    /// The functions the host provides, in the order they are declared.
+    externs: Vec<Identifier>,
+    /// The result type of the function being generated.
+    ret: Type,
+    /// Ids of the loops enclosing the code being generated, innermost last.
+    loops: Vec<usize>,
+    /// The number of loops generated so far, used to name their labels.
+    labels: usize,
 }
 
 impl Compiler {
     pub fn new() -> Self {
-        let main_fun = Function::new("main".to_string(), vec![], None, vec![], FunctionType::Script);
+        let main_fun = Function::new("_start".to_string(), vec![], None, vec![], FunctionType::Script);
         Self {
             module: Module::new(),
             current: main_fun,
+            types: HashMap::new(),
+            functions: HashMap::new(),
+            externs: vec![],
+            ret: Type::Unit,
+            loops: vec![],
+            labels: 0,
         }
     }
 
+    /// Enters a new loop and returns its id.
+    fn begin_loop(&mut self) -> usize {
+        let id = self.labels;
+        self.labels += 1;
+        self.loops.push(id);
+        id
+    }
+
+    /// Wraps the instructions of an iteration in the blocks of loop `id`.
+    fn end_loop(&mut self, id: usize, mut body: Vec<Instruction>) {
+        body.push(Instruction::Br(format!("__loop_{}", id)));
+        let loop_ = Instruction::Loop(format!("__loop_{}", id), body);
+        self.current.add_instruction(Instruction::Block(Some(format!("__break_{}", id)), vec![loop_]));
+        self.loops.pop();
+    }
+
+    /// Returns the instructions `generate` adds, for the body of a block.
+    fn nested(&mut self, generate: impl FnOnce(&mut Compiler)) -> Vec<Instruction> {
+        let outer = std::mem::take(&mut self.current.instructions);
+        generate(self);
+        std::mem::replace(&mut self.current.instructions, outer)
+    }
+
+    fn declare_functions(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            match &expr.kind {
+                ExprKind::Fun { ident, params, ret, body } => {
+                    let signature = Signature {
+                        params: params.iter().map(|p| p.ty).collect(),
+                        ret: *ret,
+                    };
+                    self.functions.insert(ident.clone(), signature);
+                    self.declare_functions(body);
+                }
+                ExprKind::Extern { ident, params, ret } => {
+                    let signature = Signature {
+                        params: params.iter().map(|p| p.ty).collect(),
+                        ret: *ret,
+                    };
+                    self.functions.insert(ident.clone(), signature);
+                    self.externs.push(ident.clone());
+                }
+                ExprKind::Block(exprs) => self.declare_functions(exprs),
+                ExprKind::IfElse { then, else_, .. } => {
+                    self.declare_functions(std::slice::from_ref(then.as_ref()));
+                    if let Some(else_) = else_ {
+                        self.declare_functions(std::slice::from_ref(else_.as_ref()));
+                    }
+                }
+                ExprKind::While { body, .. } | ExprKind::For { body, .. } => {
+                    self.declare_functions(std::slice::from_ref(body.as_ref()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Variables have unique names after resolution, any variable that
+    /// isn't a local of the current function is a global.
     pub fn is_local(&self, local: &Identifier) -> bool {
-        if self.current.params.contains(local) {
+        if self.current.params.iter().any(|(p, _)| p == local) {
             return true;
         }
 
-        self.current.locals.contains(local)
-    }
-
-    pub fn to_wat(self) -> String {
-        self.module.to_wat()
+        self.current.locals.iter().any(|(l, _)| l == local)
     }
 }
@@ -0,0 +1,260 @@
+use std::fmt;
+use crate::syntax::Span;
+use crate::codegen::watwriter::{Function, FunctionType, Instruction, Module, NumericOp, ValueType};
+use crate::codegen::watwriter::Instruction::*;
+
+// Strings live in linear memory as a 4 byte length followed by the bytes. A
+// string value is the address of its length.
+
+pub const ALLOC: &str = "__alloc";
+pub const CONCAT: &str = "__concat";
+pub const I32_TO_STR: &str = "__i32_to_str";
+pub const PRINT_STR: &str = "__print_str";
+
+/// Errors a running program reports to the host through the `trap` import,
+/// together with the source line they happened on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Trap {
+    DivisionByZero,
+}
+
+impl Trap {
+    pub fn code(self) -> i32 {
+        match self {
+            Trap::DivisionByZero => 0,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<Trap> {
+        match code {
+            0 => Some(Trap::DivisionByZero),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+/// A trap raised by the expression at `span`.
+#[derive(Debug, PartialEq)]
+pub struct TrapError {
+    pub trap: Trap,
+    pub span: Span,
+}
+
+impl fmt::Display for TrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Runtime error: {}", self.span.line, self.trap)
+    }
+}
+
+impl std::error::Error for TrapError {}
+
+/// Adds the helper functions generated code relies on to `module`.
+pub fn add_runtime(module: &mut Module) {
+    module.add_function(alloc());
+    module.add_function(concat());
+    module.add_function(i32_to_str());
+    module.add_function(print_str());
+}
+
+fn helper(name: &str, params: &[&str], result: bool, locals: &[&str], body: Vec<Instruction>) -> Function {
+    let params = params.iter().map(|p| (p.to_string(), ValueType::I32)).collect();
+    let return_type = if result { Some(ValueType::I32) } else { None };
+
+    let mut f = Function::new(name.to_string(), params, return_type, body, FunctionType::Function);
+    for l in locals {
+        f.add_local(l.to_string(), ValueType::I32);
+    }
+    f
+}
+
+fn get(local: &str) -> Instruction {
+    LocalGet(local.to_string())
+}
+
+fn set(local: &str) -> Instruction {
+    LocalSet(local.to_string())
+}
+
+fn tee(local: &str) -> Instruction {
+    LocalTee(local.to_string())
+}
+
+fn i32(op: NumericOp) -> Instruction {
+    Numeric(ValueType::I32, op)
+}
+
+/// Bump allocator, grows the memory when the heap runs past its end and
+/// traps if it can't.
+fn alloc() -> Function {
+    helper(ALLOC, &["size"], true, &["ptr"], vec![
+        GlobalGet("__heap".to_string()),
+        set("ptr"),
+        get("ptr"),
+        get("size"),
+        i32(NumericOp::Add),
+        I32Const(3),
+        i32(NumericOp::Add),
+        I32Const(-4),
+        i32(NumericOp::And),
+        GlobalSet("__heap".to_string()),
+        Block(Some("done".to_string()), vec![
+            Loop("grow".to_string(), vec![
+                GlobalGet("__heap".to_string()),
+                MemorySize,
+                I32Const(16),
+                i32(NumericOp::Shl),
+                i32(NumericOp::LeU),
+                BrIf("done".to_string()),
+                I32Const(1),
+                MemoryGrow,
+                I32Const(-1),
+                i32(NumericOp::Eq),
+                If { result: None, then: vec![Unreachable], else_: vec![] },
+                Br("grow".to_string()),
+            ]),
+        ]),
+        get("ptr"),
+    ])
+}
+
+fn concat() -> Function {
+    helper(CONCAT, &["a", "b"], true, &["a_len", "b_len", "result"], vec![
+        get("a"),
+        I32Load(0),
+        set("a_len"),
+        get("b"),
+        I32Load(0),
+        set("b_len"),
+        get("a_len"),
+        get("b_len"),
+        i32(NumericOp::Add),
+        I32Const(4),
+        i32(NumericOp::Add),
+        Call(ALLOC.to_string()),
+        tee("result"),
+        get("a_len"),
+        get("b_len"),
+        i32(NumericOp::Add),
+        I32Store(0),
+        get("result"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        get("a"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        get("a_len"),
+        MemoryCopy,
+        get("result"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        get("a_len"),
+        i32(NumericOp::Add),
+        get("b"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        get("b_len"),
+        MemoryCopy,
+        get("result"),
+    ])
+}
+
+/// Formats a signed integer in decimal.
+fn i32_to_str() -> Function {
+    helper(I32_TO_STR, &["n"], true, &["negative", "value", "len", "result", "i"], vec![
+        get("n"),
+        I32Const(0),
+        i32(NumericOp::LtS),
+        set("negative"),
+        I32Const(0),
+        get("n"),
+        i32(NumericOp::Sub),
+        get("n"),
+        get("negative"),
+        Select,
+        set("value"),
+        // Count the digits.
+        get("value"),
+        set("i"),
+        get("negative"),
+        I32Const(1),
+        i32(NumericOp::Add),
+        set("len"),
+        Block(Some("counted".to_string()), vec![
+            Loop("count".to_string(), vec![
+                get("i"),
+                I32Const(10),
+                i32(NumericOp::DivU),
+                tee("i"),
+                i32(NumericOp::Eqz),
+                BrIf("counted".to_string()),
+                get("len"),
+                I32Const(1),
+                i32(NumericOp::Add),
+                set("len"),
+                Br("count".to_string()),
+            ]),
+        ]),
+        get("len"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        Call(ALLOC.to_string()),
+        tee("result"),
+        get("len"),
+        I32Store(0),
+        // Write the digits back to front.
+        get("result"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        get("len"),
+        i32(NumericOp::Add),
+        set("i"),
+        Loop("digits".to_string(), vec![
+            get("i"),
+            I32Const(1),
+            i32(NumericOp::Sub),
+            tee("i"),
+            get("value"),
+            I32Const(10),
+            i32(NumericOp::RemU),
+            I32Const(48),
+            i32(NumericOp::Add),
+            I32Store8(0),
+            get("value"),
+            I32Const(10),
+            i32(NumericOp::DivU),
+            tee("value"),
+            BrIf("digits".to_string()),
+        ]),
+        get("negative"),
+        If {
+            result: None,
+            then: vec![
+                get("result"),
+                I32Const(45),
+                I32Store8(4),
+            ],
+            else_: vec![],
+        },
+        get("result"),
+    ])
+}
+
+/// Passes the address and length of a string to the host.
+fn print_str() -> Function {
+    helper(PRINT_STR, &["s"], false, &[], vec![
+        get("s"),
+        I32Const(4),
+        i32(NumericOp::Add),
+        get("s"),
+        I32Load(0),
+        Call("log_str".to_string()),
+    ])
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use crate::codegen::watwriter::{function_export, global_export, Conversion, Function, Import, Instruction, Label, Module, NumericOp, ValueType};
+
+// Encodes a module in the wasm binary format, the same module `to_wat` prints
+// as text. Names are only used to look up indices, no name section is written.
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const MEMORY_SECTION: u8 = 5;
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+const DATA_SECTION: u8 = 11;
+
+const FUNC_KIND: u8 = 0x00;
+const MEMORY_KIND: u8 = 0x02;
+const GLOBAL_KIND: u8 = 0x03;
+const EMPTY_BLOCK: u8 = 0x40;
+const END: u8 = 0x0b;
+
+type Signature = (Vec<ValueType>, Option<ValueType>);
+
+impl Module {
+    pub fn into_wasm(self) -> Vec<u8> {
+        let mut signatures: Vec<Signature> = vec![];
+        let mut signature_index = |signature: Signature| {
+            match signatures.iter().position(|s| s == &signature) {
+                Some(i) => i as u32,
+                None => {
+                    signatures.push(signature);
+                    signatures.len() as u32 - 1
+                }
+            }
+        };
+
+        let imports: Vec<&Import> = self.all_imports().collect();
+        let import_types: Vec<u32> = imports.iter()
+            .map(|i| signature_index((i.params.to_vec(), i.result)))
+            .collect();
+        let function_types: Vec<u32> = self.functions.iter()
+            .map(|f| signature_index((f.params.iter().map(|(_, t)| *t).collect(), f.return_type)))
+            .collect();
+
+        // Imports come first in the function index space.
+        let names = imports.iter().map(|i| i.name.to_string()).chain(self.functions.iter().map(|f| f.name.clone()));
+        let mut functions = HashMap::new();
+        for (i, name) in names.enumerate() {
+            assert!(!functions.contains_key(&name), "two functions are named '{}'", name);
+            functions.insert(name, i as u32);
+        }
+
+        // The heap pointer is global 0.
+        let mut globals = HashMap::new();
+        globals.insert("__heap".to_string(), 0);
+        for (i, g) in self.globals.iter().enumerate() {
+            globals.insert(g.name.clone(), i as u32 + 1);
+        }
+
+        let mut wasm = MAGIC.to_vec();
+        wasm.extend(VERSION);
+
+        // Types.
+        let mut types = vec![];
+        write_u32(&mut types, signatures.len() as u32);
+        for (params, result) in &signatures {
+            types.push(0x60);
+            write_u32(&mut types, params.len() as u32);
+            types.extend(params.iter().map(|p| value_type(*p)));
+            write_u32(&mut types, result.is_some() as u32);
+            types.extend(result.map(value_type));
+        }
+        write_section(&mut wasm, TYPE_SECTION, types);
+
+        // Imports.
+        let mut import_section = vec![];
+        write_u32(&mut import_section, imports.len() as u32);
+        for (import, type_index) in imports.iter().zip(import_types) {
+            write_name(&mut import_section, import.module);
+            write_name(&mut import_section, &import.name);
+            import_section.push(FUNC_KIND);
+            write_u32(&mut import_section, type_index);
+        }
+        write_section(&mut wasm, IMPORT_SECTION, import_section);
+
+        // Functions.
+        let mut function_section = vec![];
+        write_u32(&mut function_section, function_types.len() as u32);
+        for type_index in function_types {
+            write_u32(&mut function_section, type_index);
+        }
+        write_section(&mut wasm, FUNCTION_SECTION, function_section);
+
+        // Memory, one page to start with and no maximum.
+        write_section(&mut wasm, MEMORY_SECTION, vec![1, 0x00, 1]);
+
+        // Globals, all mutable.
+        let mut global_section = vec![];
+        write_u32(&mut global_section, self.globals.len() as u32 + 1);
+        global_section.extend([value_type(ValueType::I32), 0x01]);
+        write_instruction(&mut global_section, &Instruction::I32Const(self.data_end as i32));
+        global_section.push(END);
+        for g in &self.globals {
+            global_section.extend([value_type(g.value_type), 0x01]);
+            write_instruction(&mut global_section, &zero(g.value_type));
+            global_section.push(END);
+        }
+        write_section(&mut wasm, GLOBAL_SECTION, global_section);
+
+        // Exports.
+        let mut exports = vec![];
+        write_u32(&mut exports, 3 + self.exports.len() as u32 + self.globals.len() as u32);
+        write_name(&mut exports, "_start");
+        exports.push(FUNC_KIND);
+        write_u32(&mut exports, functions["_start"]);
+        write_name(&mut exports, "memory");
+        exports.push(MEMORY_KIND);
+        write_u32(&mut exports, 0);
+        write_name(&mut exports, "__alloc");
+        exports.push(FUNC_KIND);
+        write_u32(&mut exports, functions["__alloc"]);
+        for name in &self.exports {
+            write_name(&mut exports, &function_export(name));
+            exports.push(FUNC_KIND);
+            write_u32(&mut exports, functions[name]);
+        }
+        for g in &self.globals {
+            write_name(&mut exports, &global_export(&g.name));
+            exports.push(GLOBAL_KIND);
+            write_u32(&mut exports, globals[&g.name]);
+        }
+        write_section(&mut wasm, EXPORT_SECTION, exports);
+
+        // Code.
+        let mut code = vec![];
+        write_u32(&mut code, self.functions.len() as u32);
+        for f in &self.functions {
+            let body = FunctionEncoder::new(f, &functions, &globals).encode();
+            write_u32(&mut code, body.len() as u32);
+            code.extend(body);
+        }
+        write_section(&mut wasm, CODE_SECTION, code);
+
+        // Data, active segments in memory 0.
+        let mut data = vec![];
+        write_u32(&mut data, self.data.len() as u32);
+        for d in self.data {
+            data.push(0x00);
+            write_instruction(&mut data, &Instruction::I32Const(d.offset as i32));
+            data.push(END);
+            write_u32(&mut data, d.bytes.len() as u32);
+            data.extend(d.bytes);
+        }
+        write_section(&mut wasm, DATA_SECTION, data);
+
+        wasm
+    }
+}
+
+/// Encodes the body of a function, resolving names to indices.
+struct FunctionEncoder<'a> {
+    function: &'a Function,
+    functions: &'a HashMap<String, u32>,
+    globals: &'a HashMap<String, u32>,
+    locals: HashMap<&'a str, u32>,
+    /// Labels of the enclosing blocks, innermost last.
+    labels: Vec<Option<&'a Label>>,
+}
+
+impl<'a> FunctionEncoder<'a> {
+    fn new(function: &'a Function, functions: &'a HashMap<String, u32>, globals: &'a HashMap<String, u32>) -> Self {
+        // Params come first in the local index space.
+        let locals = function.params.iter()
+            .chain(&function.locals)
+            .enumerate()
+            .map(|(i, (name, _))| (name.as_str(), i as u32))
+            .collect();
+
+        Self { function, functions, globals, locals, labels: vec![] }
+    }
+
+    fn encode(mut self) -> Vec<u8> {
+        let mut body = vec![];
+
+        // Locals are declared in runs of the same type.
+        let mut runs: Vec<(u32, ValueType)> = vec![];
+        for (_, t) in &self.function.locals {
+            match runs.last_mut() {
+                Some((count, last)) if last == t => *count += 1,
+                _ => runs.push((1, *t)),
+            }
+        }
+        write_u32(&mut body, runs.len() as u32);
+        for (count, t) in runs {
+            write_u32(&mut body, count);
+            body.push(value_type(t));
+        }
+
+        self.encode_body(&mut body, &self.function.instructions);
+        body.push(END);
+        body
+    }
+
+    fn encode_body(&mut self, out: &mut Vec<u8>, instructions: &'a [Instruction]) {
+        for instruction in instructions {
+            self.encode_instruction(out, instruction);
+        }
+    }
+
+    fn encode_instruction(&mut self, out: &mut Vec<u8>, instruction: &'a Instruction) {
+        match instruction {
+            Instruction::LocalGet(l) => self.encode_index(out, 0x20, self.local(l)),
+            Instruction::LocalSet(l) => self.encode_index(out, 0x21, self.local(l)),
+            Instruction::LocalTee(l) => self.encode_index(out, 0x22, self.local(l)),
+            Instruction::GlobalGet(g) => self.encode_index(out, 0x23, self.globals[g]),
+            Instruction::GlobalSet(g) => self.encode_index(out, 0x24, self.globals[g]),
+            Instruction::Call(f) => self.encode_index(out, 0x10, self.functions[f]),
+            Instruction::Br(label) => self.encode_index(out, 0x0c, self.depth(label)),
+            Instruction::BrIf(label) => self.encode_index(out, 0x0d, self.depth(label)),
+            Instruction::Block(label, body) => {
+                out.extend([0x02, EMPTY_BLOCK]);
+                self.encode_nested(out, label.as_ref(), body);
+                out.push(END);
+            }
+            Instruction::Loop(label, body) => {
+                out.extend([0x03, EMPTY_BLOCK]);
+                self.encode_nested(out, Some(label), body);
+                out.push(END);
+            }
+            Instruction::If { result, then, else_ } => {
+                out.push(0x04);
+                out.push(result.map_or(EMPTY_BLOCK, value_type));
+                self.encode_nested(out, None, then);
+                if !else_.is_empty() {
+                    out.push(0x05);
+                    self.encode_nested(out, None, else_);
+                }
+                out.push(END);
+            }
+            _ => write_instruction(out, instruction),
+        }
+    }
+
+    fn encode_nested(&mut self, out: &mut Vec<u8>, label: Option<&'a Label>, body: &'a [Instruction]) {
+        self.labels.push(label);
+        self.encode_body(out, body);
+        self.labels.pop();
+    }
+
+    fn encode_index(&self, out: &mut Vec<u8>, opcode: u8, index: u32) {
+        out.push(opcode);
+        write_u32(out, index);
+    }
+
+    fn local(&self, name: &str) -> u32 {
+        self.locals[name]
+    }
+
+    /// How many blocks a branch to `label` leaves.
+    fn depth(&self, label: &Label) -> u32 {
+        let i = self.labels.iter()
+            .rposition(|l| *l == Some(label))
+            .unwrap_or_else(|| panic!("branch to unknown label ${}", label));
+        (self.labels.len() - 1 - i) as u32
+    }
+}
+
+/// Writes an instruction that doesn't refer to names or labels.
+fn write_instruction(out: &mut Vec<u8>, instruction: &Instruction) {
+    match instruction {
+        Instruction::I32Const(c) => {
+            out.push(0x41);
+            write_i64(out, *c as i64);
+        }
+        Instruction::I64Const(c) => {
+            out.push(0x42);
+            write_i64(out, *c);
+        }
+        Instruction::F32Const(c) => {
+            out.push(0x43);
+            out.extend(c.to_le_bytes());
+        }
+        Instruction::F64Const(c) => {
+            out.push(0x44);
+            out.extend(c.to_le_bytes());
+        }
+        Instruction::Numeric(t, op) => out.push(numeric_opcode(*t, *op)),
+        Instruction::Convert(conversion) => out.push(conversion_opcode(*conversion)),
+        // Memory accesses are followed by the alignment as a power of two.
+        Instruction::I32Load(offset) => write_memory_access(out, 0x28, 2, *offset),
+        Instruction::I32Store(offset) => write_memory_access(out, 0x36, 2, *offset),
+        Instruction::I32Store8(offset) => write_memory_access(out, 0x3a, 0, *offset),
+        Instruction::MemorySize => out.extend([0x3f, 0x00]),
+        Instruction::MemoryGrow => out.extend([0x40, 0x00]),
+        Instruction::MemoryCopy => out.extend([0xfc, 10, 0x00, 0x00]),
+        Instruction::Drop => out.push(0x1a),
+        Instruction::Select => out.push(0x1b),
+        Instruction::Return => out.push(0x0f),
+        Instruction::Unreachable => out.push(0x00),
+        _ => unreachable!("{:?} is encoded by FunctionEncoder", instruction),
+    }
+}
+
+fn write_memory_access(out: &mut Vec<u8>, opcode: u8, align: u32, offset: u32) {
+    out.push(opcode);
+    write_u32(out, align);
+    write_u32(out, offset);
+}
+
+fn numeric_opcode(t: ValueType, op: NumericOp) -> u8 {
+    use NumericOp::*;
+
+    // Integer comparisons and arithmetic are laid out the same way for i32
+    // and i64, as are the float ones for f32 and f64.
+    let (compare, arithmetic) = match t {
+        ValueType::I32 => (0x45, 0x6a),
+        ValueType::I64 => (0x50, 0x7c),
+        ValueType::F32 => (0x5b, 0x8b),
+        ValueType::F64 => (0x61, 0x99),
+    };
+
+    let opcode = match (t, op) {
+        (ValueType::I32 | ValueType::I64, _) => match op {
+            Eqz => Some(compare),
+            Eq => Some(compare + 1),
+            Ne => Some(compare + 2),
+            LtS => Some(compare + 3),
+            GtS => Some(compare + 5),
+            LeS => Some(compare + 7),
+            LeU => Some(compare + 8),
+            GeS => Some(compare + 9),
+            Add => Some(arithmetic),
+            Sub => Some(arithmetic + 1),
+            Mul => Some(arithmetic + 2),
+            DivS => Some(arithmetic + 3),
+            DivU => Some(arithmetic + 4),
+            RemS => Some(arithmetic + 5),
+            RemU => Some(arithmetic + 6),
+            And => Some(arithmetic + 7),
+            Shl => Some(arithmetic + 10),
+            Div | Neg | Lt | Gt | Le | Ge => None,
+        },
+        (ValueType::F32 | ValueType::F64, _) => match op {
+            Eq => Some(compare),
+            Ne => Some(compare + 1),
+            Lt => Some(compare + 2),
+            Gt => Some(compare + 3),
+            Le => Some(compare + 4),
+            Ge => Some(compare + 5),
+            Neg => Some(arithmetic + 1),
+            Add => Some(arithmetic + 7),
+            Sub => Some(arithmetic + 8),
+            Mul => Some(arithmetic + 9),
+            Div => Some(arithmetic + 10),
+            _ => None,
+        },
+    };
+
+    opcode.unwrap_or_else(|| panic!("{}.{} is not a wasm instruction", t.to_wat(), op.to_wat()))
+}
+
+fn conversion_opcode(conversion: Conversion) -> u8 {
+    match conversion {
+        Conversion::I64ExtendI32S => 0xac,
+        Conversion::F32ConvertI32S => 0xb2,
+        Conversion::F64ConvertI32S => 0xb7,
+        Conversion::F64ConvertI64S => 0xb9,
+        Conversion::F64PromoteF32 => 0xbb,
+    }
+}
+
+fn value_type(t: ValueType) -> u8 {
+    match t {
+        ValueType::I32 => 0x7f,
+        ValueType::I64 => 0x7e,
+        ValueType::F32 => 0x7d,
+        ValueType::F64 => 0x7c,
+    }
+}
+
+fn zero(t: ValueType) -> Instruction {
+    match t {
+        ValueType::I32 => Instruction::I32Const(0),
+        ValueType::I64 => Instruction::I64Const(0),
+        ValueType::F32 => Instruction::F32Const(0.0),
+        ValueType::F64 => Instruction::F64Const(0.0),
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    out.push(id);
+    write_u32(out, contents.len() as u32);
+    out.extend(contents);
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend(name.bytes());
+}
+
+/// Unsigned LEB128.
+fn write_u32(out: &mut Vec<u8>, mut n: u32) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128, also used for i32 constants.
+fn write_i64(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        let done = (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::watwriter::FunctionType;
+
+    fn encode_u32(n: u32) -> Vec<u8> {
+        let mut out = vec![];
+        write_u32(&mut out, n);
+        out
+    }
+
+    fn encode_i64(n: i64) -> Vec<u8> {
+        let mut out = vec![];
+        write_i64(&mut out, n);
+        out
+    }
+
+    #[test]
+    fn leb128() {
+        assert_eq!(encode_u32(0), [0x00]);
+        assert_eq!(encode_u32(127), [0x7f]);
+        assert_eq!(encode_u32(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(encode_i64(-1), [0x7f]);
+        assert_eq!(encode_i64(63), [0x3f]);
+        assert_eq!(encode_i64(64), [0xc0, 0x00]);
+        assert_eq!(encode_i64(-123456), [0xc0, 0xbb, 0x78]);
+    }
+
+    #[test]
+    fn numeric_opcodes() {
+        assert_eq!(numeric_opcode(ValueType::I32, NumericOp::Add), 0x6a);
+        assert_eq!(numeric_opcode(ValueType::I64, NumericOp::RemS), 0x81);
+        assert_eq!(numeric_opcode(ValueType::I64, NumericOp::GeS), 0x59);
+        assert_eq!(numeric_opcode(ValueType::F32, NumericOp::Neg), 0x8c);
+        assert_eq!(numeric_opcode(ValueType::F64, NumericOp::Div), 0xa3);
+        assert_eq!(numeric_opcode(ValueType::F64, NumericOp::Ge), 0x66);
+    }
+
+    #[test]
+    fn branch_depths() {
+        let f = Function::new("f".to_string(), vec![("n".to_string(), ValueType::I32)], None, vec![
+            Instruction::Block(Some("out".to_string()), vec![
+                Instruction::Loop("again".to_string(), vec![
+                    Instruction::LocalGet("n".to_string()),
+                    Instruction::BrIf("out".to_string()),
+                    Instruction::Br("again".to_string()),
+                ]),
+            ]),
+        ], FunctionType::Function);
+
+        let body = FunctionEncoder::new(&f, &HashMap::new(), &HashMap::new()).encode();
+        assert_eq!(body, [
+            0x00,
+            0x02, 0x40,
+            0x03, 0x40,
+            0x20, 0x00,
+            0x0d, 0x01,
+            0x0c, 0x00,
+            END,
+            END,
+            END,
+        ]);
+    }
+
+    #[test]
+    fn module_header() {
+        let mut m = Module::new();
+        m.add_function(Function::new("_start".to_string(), vec![], None, vec![], FunctionType::Script));
+        m.add_function(Function::new("__alloc".to_string(), vec![], None, vec![], FunctionType::Function));
+
+        let wasm = m.into_wasm();
+        assert_eq!(&wasm[..8], b"\0asm\x01\0\0\0");
+        assert_eq!(wasm[8], TYPE_SECTION);
+    }
+}
@@ -1,46 +1,119 @@
+mod binary;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
 use crate::syntax::ast::Identifier;
 
+/// The namespace the runtime's functions are imported from.
+pub const RUNTIME_MODULE: &str = "env";
+/// The namespace the functions a program declares with `extern fun` are
+/// imported from, so they can't replace the runtime's.
+pub const HOST_MODULE: &str = "host";
+
+/// Functions the runtime needs the host to provide in `RUNTIME_MODULE`, they
+/// come first in the function index space followed by the ones the program
+/// declares.
+pub const IMPORTS: &[Import] = &[
+    Import::runtime("log", &[ValueType::I32], None),
+    Import::runtime("log_i64", &[ValueType::I64], None),
+    Import::runtime("log_str", &[ValueType::I32, ValueType::I32], None),
+    Import::runtime("log_f64", &[ValueType::F64], None),
+    Import::runtime("trap", &[ValueType::I32; 5], None),
+    Import::runtime("i64_to_str", &[ValueType::I64], Some(ValueType::I32)),
+    Import::runtime("f32_to_str", &[ValueType::F32], Some(ValueType::I32)),
+    Import::runtime("f64_to_str", &[ValueType::F64], Some(ValueType::I32)),
+];
+
 #[derive(Clone)]
 pub struct Module {
+    /// The host functions the program declares, after `IMPORTS`.
+    pub imports: Vec<Import>,
     pub globals: Vec<Global>,
-    pub data: Vec<String>,
+    pub data: Vec<Data>,
     pub functions: Vec<Function>,
+    /// The functions the host can call.
+    pub exports: Vec<FunctionName>,
+    strings: HashMap<String, u32>,
+    data_end: u32,
 }
 
 impl Module {
     pub fn new() -> Self {
-        Self { globals: vec![], data: vec![], functions: vec![] }
+        Self {
+            imports: vec![],
+            globals: vec![],
+            data: vec![],
+            functions: vec![],
+            exports: vec![],
+            strings: HashMap::new(),
+            data_end: 0,
+        }
+    }
+
+    pub fn add_import(&mut self, import: Import) {
+        self.imports.push(import);
+    }
+
+    /// Every import, the runtime's first.
+    pub fn all_imports(&self) -> impl Iterator<Item = &Import> {
+        IMPORTS.iter().chain(&self.imports)
     }
 
     pub fn add_global(&mut self, global: Global) {
         self.globals.push(global);
     }
 
-    pub fn add_data(&mut self, str: String) {
-        self.data.push(str);
+    /// Stores `str` in linear memory prefixed with its length and returns its
+    /// address. Equal strings share one data segment.
+    pub fn add_data(&mut self, str: String) -> u32 {
+        if let Some(&offset) = self.strings.get(&str) {
+            return offset;
+        }
+
+        let offset = self.data_end;
+        self.strings.insert(str.clone(), offset);
+
+        let mut bytes = (str.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(str.into_bytes());
+
+        // Keep every segment 4-byte aligned.
+        self.data_end = align(offset + bytes.len() as u32);
+        self.data.push(Data { offset, bytes });
+
+        offset
     }
 
     pub fn add_function(&mut self, fun: Function) {
         self.functions.push(fun);
     }
 
+    pub fn export_function(&mut self, name: FunctionName) {
+        self.exports.push(name);
+    }
+
     pub fn to_wat(self) -> String {
         let mut prefix = "(module\n".to_string();
 
-        // Print function.
-        prefix += "(import \"env\" \"log\" (func $log (param i32)))\n";
+        // Host functions.
+        for import in self.all_imports() {
+            prefix += &import.to_wat();
+        }
 
         // Memory.
         prefix += "(memory $mem 1)\n";
 
+        // The heap starts right after the data segments.
+        prefix += &format!("(global $__heap (mut i32) (i32.const {}))\n", self.data_end);
+
         // Globals.
+        let global_names: Vec<String> = self.globals.iter().map(|g| g.name.clone()).collect();
         for g in self.globals {
             prefix += &g.to_wat();
         }
 
         // Data.
         for d in self.data {
-            prefix += &format!("(data (i32.const 0) \"{}\")\n", d);
+            prefix += &d.to_wat();
         }
 
         // Functions.
@@ -48,17 +121,97 @@ impl Module {
             prefix += &f.to_wat();
         }
 
-        // Export main function.
-        prefix += &format!("(export \"main\" (func $main))\n");
+        // Export script function.
+        prefix += &format!("(export \"_start\" (func $_start))\n");
 
         // Export memory.
         prefix += &format!("(export \"memory\" (memory $mem))\n");
 
+        // Export the allocator so the host can pass strings in.
+        prefix += "(export \"__alloc\" (func $__alloc))\n";
+
+        // Export the program's functions so the host can call them.
+        for name in self.exports {
+            prefix += &format!("(export \"{}\" (func {}))\n", function_export(&name), id(&name));
+        }
+
+        // Export globals so the host can read them after a run.
+        for name in global_names {
+            prefix += &format!("(export \"{}\" (global {}))\n", global_export(&name), id(&name));
+        }
+
         prefix + ")"
     }
 }
 
+/// The name a global is exported as, variables may have the names of other
+/// exports.
+pub fn global_export(name: &str) -> String {
+    format!("global.{}", name)
+}
+
+/// The name a function of the program is exported as.
+pub fn function_export(name: &str) -> String {
+    format!("fun.{}", name)
+}
+
+#[derive(Clone)]
+pub struct Import {
+    pub module: &'static str,
+    pub name: Cow<'static, str>,
+    pub params: Cow<'static, [ValueType]>,
+    pub result: Option<ValueType>,
+}
+
+impl Import {
+    pub fn new(name: String, params: Vec<ValueType>, result: Option<ValueType>) -> Self {
+        Import { module: HOST_MODULE, name: Cow::Owned(name), params: Cow::Owned(params), result }
+    }
+
+    const fn runtime(name: &'static str, params: &'static [ValueType], result: Option<ValueType>) -> Self {
+        Import { module: RUNTIME_MODULE, name: Cow::Borrowed(name), params: Cow::Borrowed(params), result }
+    }
+
+    pub fn to_wat(&self) -> String {
+        let mut signature = String::new();
+        if !self.params.is_empty() {
+            let params: Vec<_> = self.params.iter().map(|p| p.to_wat()).collect();
+            signature += &format!(" (param {})", params.join(" "));
+        }
+        if let Some(result) = self.result {
+            signature += &format!(" (result {})", result.to_wat());
+        }
+
+        format!("(import \"{}\" \"{}\" (func {}{}))\n", self.module, self.name, id(&self.name), signature)
+    }
+}
+
+fn align(offset: u32) -> u32 {
+    (offset + 3) & !3
+}
+
 #[derive(Clone)]
+pub struct Data {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Data {
+    pub fn to_wat(self) -> String {
+        let mut escaped = String::new();
+        for b in self.bytes {
+            if (b.is_ascii_graphic() || b == b' ') && b != b'"' && b != b'\\' {
+                escaped.push(b as char);
+            } else {
+                escaped += &format!("\\{:02x}", b);
+            }
+        }
+
+        format!("(data (i32.const {}) \"{}\")\n", self.offset, escaped)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ValueType {
     I32,
     I64,
@@ -66,6 +219,17 @@ pub enum ValueType {
     F64,
 }
 
+impl ValueType {
+    pub fn to_wat(self) -> &'static str {
+        match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Global {
     pub name: String,
@@ -75,32 +239,202 @@ pub struct Global {
 
 impl Global {
     pub fn to_wat(self) -> String {
-        format!("(global ${} (mut i32) (i32.const 0))\n", self.name)
+        let value_type = self.value_type.to_wat();
+        format!("(global {} (mut {}) ({}.const 0))\n", id(&self.name), value_type, value_type)
     }
 }
 
 pub type FunctionName = String;
-
-#[derive(Clone)]
-pub enum Statement {
-    Const(i32),
+pub type Label = String;
+
+/// A wasm instruction. Control flow instructions hold their bodies, so code
+/// can be inspected and transformed before it is rendered.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    I32Const(i32),
+    I64Const(i64),
+    F32Const(f32),
+    F64Const(f64),
+    LocalGet(Identifier),
+    LocalSet(Identifier),
+    LocalTee(Identifier),
+    GlobalGet(Identifier),
+    GlobalSet(Identifier),
+    /// A numeric instruction on operands of the given type, e.g. `i32.add`.
+    Numeric(ValueType, NumericOp),
+    Convert(Conversion),
+    /// Memory accesses, with a constant offset added to the address.
+    I32Load(u32),
+    I32Store(u32),
+    I32Store8(u32),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
     Call(FunctionName),
-    String(String),
+    Drop,
+    Select,
+    Return,
+    Unreachable,
+    Br(Label),
+    BrIf(Label),
+    Block(Option<Label>, Vec<Instruction>),
+    Loop(Label, Vec<Instruction>),
+    If {
+        result: Option<ValueType>,
+        then: Vec<Instruction>,
+        else_: Vec<Instruction>,
+    },
 }
 
-impl Statement {
-    fn to_wat(self) -> String {
-        return match self {
-            Statement::Const(c) => {
-                format!("i32.const {}\n", c)
+impl Instruction {
+    fn to_wat(&self) -> String {
+        match self {
+            Instruction::I32Const(c) => format!("i32.const {}\n", c),
+            Instruction::I64Const(c) => format!("i64.const {}\n", c),
+            Instruction::F32Const(c) => format!("f32.const {}\n", c),
+            Instruction::F64Const(c) => format!("f64.const {}\n", c),
+            Instruction::LocalGet(l) => format!("local.get {}\n", id(l)),
+            Instruction::LocalSet(l) => format!("local.set {}\n", id(l)),
+            Instruction::LocalTee(l) => format!("local.tee {}\n", id(l)),
+            Instruction::GlobalGet(g) => format!("global.get {}\n", id(g)),
+            Instruction::GlobalSet(g) => format!("global.set {}\n", id(g)),
+            Instruction::Numeric(value_type, op) => format!("{}.{}\n", value_type.to_wat(), op.to_wat()),
+            Instruction::Convert(conversion) => format!("{}\n", conversion.to_wat()),
+            Instruction::I32Load(offset) => memory_access("i32.load", *offset),
+            Instruction::I32Store(offset) => memory_access("i32.store", *offset),
+            Instruction::I32Store8(offset) => memory_access("i32.store8", *offset),
+            Instruction::MemorySize => "memory.size\n".to_string(),
+            Instruction::MemoryGrow => "memory.grow\n".to_string(),
+            Instruction::MemoryCopy => "memory.copy\n".to_string(),
+            Instruction::Call(f) => format!("call {}\n", id(f)),
+            Instruction::Drop => "drop\n".to_string(),
+            Instruction::Select => "select\n".to_string(),
+            Instruction::Return => "return\n".to_string(),
+            Instruction::Unreachable => "unreachable\n".to_string(),
+            Instruction::Br(label) => format!("br ${}\n", label),
+            Instruction::BrIf(label) => format!("br_if ${}\n", label),
+            Instruction::Block(label, body) => {
+                let label = label.as_ref().map_or(String::new(), |l| format!(" ${}", l));
+                format!("(block{}\n{})\n", label, body_to_wat(body))
             }
-            Statement::String(s) => {
-                format!("{}\n", s)
+            Instruction::Loop(label, body) => format!("(loop ${}\n{})\n", label, body_to_wat(body)),
+            Instruction::If { result, then, else_ } => {
+                let result = result.map_or(String::new(), |r| format!(" (result {})", r.to_wat()));
+                let mut wat = format!("(if{}\n(then\n{})", result, body_to_wat(then));
+                if !else_.is_empty() {
+                    wat += &format!("\n(else\n{})", body_to_wat(else_));
+                }
+                wat + ")\n"
             }
-            Statement::Call(f) => {
-                format!("call ${}\n", f)
-            }
-        };
+        }
+    }
+}
+
+fn memory_access(name: &str, offset: u32) -> String {
+    if offset == 0 {
+        format!("{}\n", name)
+    } else {
+        format!("{} offset={}\n", name, offset)
+    }
+}
+
+fn body_to_wat(body: &[Instruction]) -> String {
+    body.iter().map(Instruction::to_wat).collect()
+}
+
+/// `$name`, the name of a function, local or global in the text format.
+/// Characters that can't be part of one, such as letters outside ASCII, are
+/// written as their code point in hex between `%`s, which Teal names can't
+/// contain.
+fn id(name: &str) -> String {
+    let mut id = "$".to_string();
+    for c in name.chars() {
+        if c.is_ascii_graphic() && !"\"(),;[]{}%".contains(c) {
+            id.push(c);
+        } else {
+            id += &format!("%{:x}%", c as u32);
+        }
+    }
+    id
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumericOp {
+    Add,
+    Sub,
+    Mul,
+    /// Float division.
+    Div,
+    DivS,
+    DivU,
+    RemS,
+    RemU,
+    And,
+    Shl,
+    Neg,
+    Eqz,
+    Eq,
+    Ne,
+    /// Float comparisons.
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    LtS,
+    GtS,
+    LeS,
+    LeU,
+    GeS,
+}
+
+impl NumericOp {
+    fn to_wat(self) -> &'static str {
+        match self {
+            NumericOp::Add => "add",
+            NumericOp::Sub => "sub",
+            NumericOp::Mul => "mul",
+            NumericOp::Div => "div",
+            NumericOp::DivS => "div_s",
+            NumericOp::DivU => "div_u",
+            NumericOp::RemS => "rem_s",
+            NumericOp::RemU => "rem_u",
+            NumericOp::And => "and",
+            NumericOp::Shl => "shl",
+            NumericOp::Neg => "neg",
+            NumericOp::Eqz => "eqz",
+            NumericOp::Eq => "eq",
+            NumericOp::Ne => "ne",
+            NumericOp::Lt => "lt",
+            NumericOp::Gt => "gt",
+            NumericOp::Le => "le",
+            NumericOp::Ge => "ge",
+            NumericOp::LtS => "lt_s",
+            NumericOp::GtS => "gt_s",
+            NumericOp::LeS => "le_s",
+            NumericOp::LeU => "le_u",
+            NumericOp::GeS => "ge_s",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conversion {
+    I64ExtendI32S,
+    F32ConvertI32S,
+    F64ConvertI32S,
+    F64ConvertI64S,
+    F64PromoteF32,
+}
+
+impl Conversion {
+    fn to_wat(self) -> &'static str {
+        match self {
+            Conversion::I64ExtendI32S => "i64.extend_i32_s",
+            Conversion::F32ConvertI32S => "f32.convert_i32_s",
+            Conversion::F64ConvertI32S => "f64.convert_i32_s",
+            Conversion::F64ConvertI64S => "f64.convert_i64_s",
+            Conversion::F64PromoteF32 => "f64.promote_f32",
+        }
     }
 }
 
@@ -113,56 +447,54 @@ pub enum FunctionType {
 #[derive(Clone)]
 pub struct Function {
     name: String,
-    pub params: Vec<Identifier>,
+    pub params: Vec<(Identifier, ValueType)>,
     return_type: Option<ValueType>,
-    pub locals: Vec<Identifier>,
-    statements: Vec<Statement>,
+    pub locals: Vec<(Identifier, ValueType)>,
+    pub instructions: Vec<Instruction>,
     pub function_type: FunctionType,
 }
 
 impl Function {
     pub fn new(
         name: String,
-        params: Vec<Identifier>,
+        params: Vec<(Identifier, ValueType)>,
         return_type: Option<ValueType>,
-        statements: Vec<Statement>,
+        instructions: Vec<Instruction>,
         function_type: FunctionType) -> Self {
-        Self { name, params, return_type, locals: vec![], statements, function_type }
+        Self { name, params, return_type, locals: vec![], instructions, function_type }
     }
 
-    pub fn add_local(&mut self, local: Identifier) {
-        self.locals.push(local);
+    pub fn add_local(&mut self, local: Identifier, value_type: ValueType) {
+        self.locals.push((local, value_type));
     }
 
-    pub fn add_statement(&mut self, statement: Statement) {
-        self.statements.push(statement);
+    pub fn add_instruction(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
     }
 
     pub fn to_wat(self) -> String {
         let mut prefix = "(func ".to_string();
 
-        prefix += &format!("${} ", self.name);
+        prefix += &format!("{} ", id(&self.name));
 
         // Params.
-        for p in self.params {
-            prefix += &format!("(param ${} i32) ", p);
+        for (p, value_type) in self.params {
+            prefix += &format!("(param {} {}) ", id(&p), value_type.to_wat());
         }
         prefix += "\n";
 
         // Return type.
-        if self.return_type.is_some() {
-            prefix += "(result i32)\n";
+        if let Some(return_type) = self.return_type {
+            prefix += &format!("(result {})\n", return_type.to_wat());
         }
 
         // Local declarations.
-        for l in self.locals {
-            prefix += &format!("(local ${} i32)\n", l);
+        for (l, value_type) in self.locals {
+            prefix += &format!("(local {} {})\n", id(&l), value_type.to_wat());
         }
 
-        // Statements.
-        for s in self.statements {
-            prefix += &s.to_wat();
-        }
+        // Instructions.
+        prefix += &body_to_wat(&self.instructions);
 
         prefix += ")\n";
 
@@ -177,7 +509,86 @@ mod tests {
     #[test]
     fn module_to_wat() {
         let m = Module::new();
-        assert_eq!(m.to_wat(), "(module)");
+        assert_eq!(m.to_wat(), r#"(module
+(import "env" "log" (func $log (param i32)))
+(import "env" "log_i64" (func $log_i64 (param i64)))
+(import "env" "log_str" (func $log_str (param i32 i32)))
+(import "env" "log_f64" (func $log_f64 (param f64)))
+(import "env" "trap" (func $trap (param i32 i32 i32 i32 i32)))
+(import "env" "i64_to_str" (func $i64_to_str (param i64) (result i32)))
+(import "env" "f32_to_str" (func $f32_to_str (param f32) (result i32)))
+(import "env" "f64_to_str" (func $f64_to_str (param f64) (result i32)))
+(memory $mem 1)
+(global $__heap (mut i32) (i32.const 0))
+(export "_start" (func $_start))
+(export "memory" (memory $mem))
+(export "__alloc" (func $__alloc))
+)"#);
+    }
+
+    #[test]
+    fn escape_names() {
+        let wat = crate::compile_to_wat("let é = 1;\nfun dé(n: i32) -> i32 { n }\nprint dé(é);").unwrap();
+        assert!(wat.contains("(func $d%e9% "), "{}", wat);
+        wasmer::wat2wasm(wat.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn add_data() {
+        let mut m = Module::new();
+        assert_eq!(m.add_data("Hello".to_string()), 0);
+        assert_eq!(m.add_data("World!".to_string()), 12);
+        assert_eq!(m.add_data("Hello".to_string()), 0);
+        assert_eq!(m.data.len(), 2);
+    }
+
+    #[test]
+    fn data_to_wat() {
+        let d = Data { offset: 8, bytes: b"\x02\0\0\0\"\n".to_vec() };
+        assert_eq!(d.to_wat(), "(data (i32.const 8) \"\\02\\00\\00\\00\\22\\0a\")\n");
+    }
+
+    #[test]
+    fn nested_instructions_to_wat() {
+        let body = vec![
+            Instruction::Block(Some("out".to_string()), vec![
+                Instruction::Loop("again".to_string(), vec![
+                    Instruction::LocalGet("n".to_string()),
+                    Instruction::Numeric(ValueType::I32, NumericOp::Eqz),
+                    Instruction::BrIf("out".to_string()),
+                    Instruction::Br("again".to_string()),
+                ]),
+            ]),
+            Instruction::I32Const(1),
+            Instruction::If {
+                result: Some(ValueType::I64),
+                then: vec![Instruction::I64Const(2)],
+                else_: vec![Instruction::I64Const(3)],
+            },
+            Instruction::I32Store8(4),
+        ];
+        let f = Function::new("f".to_string(), vec![("n".to_string(), ValueType::I32)], None, body, FunctionType::Function);
+
+        assert_eq!(f.to_wat(), r#"(func $f (param $n i32) 
+(block $out
+(loop $again
+local.get $n
+i32.eqz
+br_if $out
+br $again
+)
+)
+i32.const 1
+(if (result i64)
+(then
+i64.const 2
+)
+(else
+i64.const 3
+))
+i32.store8 offset=4
+)
+"#);
     }
 
     // #[test]
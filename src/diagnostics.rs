@@ -0,0 +1,324 @@
+use std::fmt;
+use crate::error::TealError;
+use crate::resolve::error::ResolveError;
+use crate::syntax::{ParserError, Span, SyntaxError};
+use crate::types::error::TypeError;
+
+// Renders errors the way the test files spell them, followed by the source
+// lines they point at:
+//
+//   [line 2] Error at 'true + 1': cannot apply '+' to bool and i32
+//     |
+//   2 | print true + 1;
+//     |       ^^^^^^^^
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Error,
+    RuntimeError,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "Error"),
+            Level::RuntimeError => write!(f, "Runtime error"),
+            Level::Warning => write!(f, "Warning"),
+            Level::Note => write!(f, "Note"),
+        }
+    }
+}
+
+/// Whether rendered diagnostics are coloured with ANSI escape codes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Plain,
+    Ansi,
+}
+
+/// A span of the source a diagnostic points at. The primary label is named
+/// in the header and underlined with `^`, other labels with `-`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(level: Level, message: impl Into<String>) -> Self {
+        Diagnostic { level, message: message.into(), labels: vec![], notes: vec![] }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic::new(Level::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic::new(Level::Warning, message)
+    }
+
+    pub fn note(message: impl Into<String>) -> Self {
+        Diagnostic::new(Level::Note, message)
+    }
+
+    /// Adds a label, the first one added is the primary label.
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        let primary = self.labels.is_empty();
+        self.labels.push(Label { span, message: message.into(), primary });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    fn primary(&self) -> Option<&Label> {
+        self.labels.iter().find(|l| l.primary)
+    }
+
+    pub fn render(&self, source: &str, style: Style) -> String {
+        let level_color = match self.level {
+            Level::Error | Level::RuntimeError => RED,
+            Level::Warning => YELLOW,
+            Level::Note => CYAN,
+        };
+
+        // Header.
+        let mut out = String::new();
+        if let Some(primary) = self.primary() {
+            out += &format!("[line {}] ", primary.span.line);
+        }
+        out += &paint(style, level_color, &self.level.to_string());
+        if let Some(primary) = self.primary() {
+            out += &location(source, primary.span);
+        }
+        out += &paint(style, BOLD, &format!(": {}", self.message));
+        out.push('\n');
+
+        // Source lines, in the order they appear.
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|l| (l.span.line, l.span.column));
+
+        let gutter = labels.iter().map(|l| l.span.line).max().unwrap_or(0).to_string().len();
+        let bar = |line: &str| paint(style, BLUE, &format!("{:>width$} |", line, width = gutter));
+
+        if !labels.is_empty() {
+            out += &format!("{}\n", bar(""));
+        }
+
+        let mut previous_line = None;
+        for label in labels {
+            let (line_start, text) = source_line(source, label.span.start);
+            if previous_line != Some(label.span.line) {
+                out += &format!("{} {}\n", bar(&label.span.line.to_string()), text);
+                previous_line = Some(label.span.line);
+            }
+
+            // Keep tabs so the underline lines up with the source.
+            let start = label.span.start.min(source.len());
+            let indent: String = source[line_start..start].chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            let end = label.span.end.clamp(start, line_start + text.len());
+            let width = source[start..end].chars().count().max(1);
+
+            let (marker, color) = if label.primary { ('^', level_color) } else { ('-', BLUE) };
+            let mut underline = marker.to_string().repeat(width);
+            if !label.message.is_empty() {
+                underline += &format!(" {}", label.message);
+            }
+            out += &format!("{} {}{}\n", bar(""), indent, paint(style, color, &underline));
+        }
+
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", " ".repeat(gutter), note);
+        }
+
+        out
+    }
+}
+
+impl TealError {
+    /// One diagnostic for every error, in the order they were found.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            TealError::Parser(errors) => errors.iter().map(Diagnostic::from).collect(),
+            TealError::Resolve(errors) => errors.iter().map(Diagnostic::from).collect(),
+            TealError::Type(e) => vec![e.into()],
+            TealError::Codegen(message) => vec![Diagnostic::error(format!("invalid module: {}", message))],
+            TealError::Trap(e) => {
+                vec![Diagnostic::new(Level::RuntimeError, e.trap.to_string()).with_label(e.span, "")]
+            }
+            TealError::Limit(limit) => vec![Diagnostic::new(Level::RuntimeError, limit.to_string())],
+            TealError::Runtime(message) => vec![Diagnostic::new(Level::RuntimeError, message.clone())],
+            TealError::Link(message) | TealError::Call(message) => vec![Diagnostic::error(message.clone())],
+        }
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(error: &SyntaxError) -> Self {
+        Diagnostic::error(error.message()).with_label(error.span(), "")
+    }
+}
+
+impl From<&ParserError> for Diagnostic {
+    fn from(error: &ParserError) -> Self {
+        Diagnostic::error(error.message()).with_label(error.span(), "")
+    }
+}
+
+impl From<&ResolveError> for Diagnostic {
+    fn from(error: &ResolveError) -> Self {
+        let diagnostic = Diagnostic::error(error.message()).with_label(error.span(), "");
+        match error.previous() {
+            Some(previous) => diagnostic.with_label(previous, "first declared here"),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&TypeError> for Diagnostic {
+    fn from(error: &TypeError) -> Self {
+        Diagnostic::error(error.message()).with_label(error.span(), "")
+    }
+}
+
+/// Names the code at `span` in the header, e.g. ` at '+'`. The only empty
+/// tokens before the end are changes of indentation.
+fn location(source: &str, span: Span) -> String {
+    if span.start >= source.len() {
+        return " at end".to_string();
+    }
+
+    match source.get(span.start..span.end) {
+        Some("") => " at indentation".to_string(),
+        None => String::new(),
+        Some(text) if text.contains('\n') => String::new(),
+        Some(text) => format!(" at '{}'", text),
+    }
+}
+
+/// The offset of the line containing `offset` and its text.
+fn source_line(source: &str, offset: usize) -> (usize, &str) {
+    let offset = offset.min(source.len());
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+    (start, source[start..end].trim_end_matches('\r'))
+}
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+fn paint(style: Style, color: &str, text: &str) -> String {
+    match style {
+        Style::Plain => text.to_string(),
+        Style::Ansi => format!("{}{}{}", color, text, RESET),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_error() {
+        let source = "let x = 1;\nprint true + x;\n";
+        let diagnostic = Diagnostic::error("cannot apply '+' to bool and i32")
+            .with_label(Span::new(17, 25, 2, 7), "");
+
+        assert_eq!(diagnostic.render(source, Style::Plain), "\
+[line 2] Error at 'true + x': cannot apply '+' to bool and i32
+  |
+2 | print true + x;
+  |       ^^^^^^^^
+");
+    }
+
+    #[test]
+    fn render_labels_and_notes() {
+        let source = "let s = \"a\";\n\nlet n: i32 = s;\n";
+        let diagnostic = Diagnostic::warning("narrowing assignment")
+            .with_label(Span::new(27, 28, 3, 14), "this is a string")
+            .with_label(Span::new(4, 5, 1, 5), "declared here")
+            .with_note("strings can't be used as numbers");
+
+        assert_eq!(diagnostic.render(source, Style::Plain), "\
+[line 3] Warning at 's': narrowing assignment
+  |
+1 | let s = \"a\";
+  |     - declared here
+3 | let n: i32 = s;
+  |              ^ this is a string
+  = note: strings can't be used as numbers
+");
+    }
+
+    #[test]
+    fn render_at_end_of_file() {
+        let diagnostic = Diagnostic::error("expected ')' but found end of file")
+            .with_label(Span::new(7, 7, 1, 8), "");
+
+        assert_eq!(diagnostic.render("print (", Style::Plain), "\
+[line 1] Error at end: expected ')' but found end of file
+  |
+1 | print (
+  |        ^
+");
+    }
+
+    #[test]
+    fn render_at_indentation() {
+        let source = "  print 1\nprint 2\n";
+        let error = crate::check(source).unwrap_err();
+
+        assert!(error.diagnostics()[0].render(source, Style::Plain)
+            .starts_with("[line 1] Error at indentation: unexpected indent\n"));
+    }
+
+    #[test]
+    fn render_without_location() {
+        let diagnostic = Diagnostic::new(Level::RuntimeError, "out of memory");
+        assert_eq!(diagnostic.render("", Style::Plain), "Runtime error: out of memory\n");
+    }
+
+    #[test]
+    fn render_compile_error() {
+        let source = "print 1;\nlet x = ;";
+        let error = crate::compile(source).unwrap_err();
+        let error = error.downcast_ref::<TealError>().unwrap();
+
+        assert_eq!(error.diagnostics()[0].render(source, Style::Plain), "\
+[line 2] Error at ';': unexpected ';'
+  |
+2 | let x = ;
+  |         ^
+");
+    }
+
+    #[test]
+    fn render_ansi() {
+        let diagnostic = Diagnostic::error("unexpected ';'").with_label(Span::new(8, 9, 1, 9), "");
+        let rendered = diagnostic.render("let x = ;", Style::Ansi);
+
+        assert!(rendered.starts_with("[line 1] \x1b[1;31mError\x1b[0m at ';'"));
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+    }
+}
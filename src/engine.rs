@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use wasmer::{CompilerConfig, Cranelift, Exports, ImportObject, Memory, Module, RuntimeError, Store, Universal, WASM_MAX_PAGES};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_middlewares::Metering;
+use crate::codegen::{externs, function_export, generate_assembly, generate_wasm, global_export, signatures, Signature, HOST_MODULE, RUNTIME_MODULE};
+use crate::diagnostics::Style;
+use crate::error::TealError;
+use crate::host::{teal_value, wasm_value, HostFunction};
+use crate::interp::value::Value;
+use crate::limits::{fuel_cost, Instrumentation, Limit, Limits, DEPTH, EXHAUSTED, MAX_DEPTH, MAX_PAGES};
+use crate::output::{Buffer, Output};
+use crate::syntax::ast::{Identifier, Program, Type};
+use crate::{codegen_error, dump_tokens, front_end, runtime_imports, Emit, SharedOutput};
+
+/// Compiles Teal programs for a host to run. The programs an engine compiles
+/// without limits share its store.
+pub struct Engine {
+    store: Store,
+    /// The limits of the programs compiled from now on.
+    limits: Limits,
+    /// The stages of every compiled program to write to `debug`.
+    dumps: Vec<Emit>,
+    debug: Box<dyn Write>,
+    /// Where the errors of a program that doesn't compile are rendered,
+    /// besides being returned.
+    diagnostics: Option<(Box<dyn Write>, Style)>,
+    /// The functions programs can declare with `extern fun`.
+    host_functions: HashMap<Identifier, HostFunction>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            store: Store::default(),
+            limits: Limits::default(),
+            dumps: vec![],
+            debug: Box::new(io::sink()),
+            diagnostics: None,
+            host_functions: HashMap::new(),
+        }
+    }
+
+    /// Makes `function` available to programs that declare it, e.g. as
+    /// `extern fun name(a: i32) -> string;` for a `signature` of
+    /// `(i32) -> string`. An error it returns stops the program.
+    pub fn register(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        function: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let host = HostFunction { name: name.to_string(), signature, function: Arc::new(function) };
+        self.host_functions.insert(name.to_string(), host);
+        self
+    }
+
+    /// Limits what the programs compiled from now on can use each time
+    /// they're run or called, nothing is limited by default. A program that
+    /// runs past a limit stops with `TealError::Limit`.
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Writes the `stage` of every program compiled from now on to the debug
+    /// output.
+    pub fn dump(&mut self, stage: Emit) -> &mut Self {
+        self.dumps.push(stage);
+        self
+    }
+
+    /// Where the stages passed to `dump` are written, nowhere by default.
+    pub fn debug_output(&mut self, to: impl Write + 'static) -> &mut Self {
+        self.debug = Box::new(to);
+        self
+    }
+
+    /// Renders the errors of programs that don't compile to `to`.
+    pub fn diagnostics_output(&mut self, to: impl Write + 'static, style: Style) -> &mut Self {
+        self.diagnostics = Some((Box::new(to), style));
+        self
+    }
+
+    pub fn compile(&mut self, source: &str) -> Result<CompiledProgram, TealError> {
+        let result = self.compile_source(source);
+
+        if let (Err(error), Some((to, style))) = (&result, &mut self.diagnostics) {
+            for diagnostic in error.diagnostics() {
+                // The error is returned, rendering it is best effort.
+                let _ = write!(to, "{}", diagnostic.render(source, *style));
+            }
+        }
+
+        result
+    }
+
+    fn compile_source(&mut self, source: &str) -> Result<CompiledProgram, TealError> {
+        if self.dumps.contains(&Emit::Tokens) {
+            self.write_debug(&dump_tokens(source));
+        }
+
+        let program = front_end(source)?;
+        if self.dumps.contains(&Emit::Ast) {
+            self.write_debug(&format!("{:#?}\n", program));
+        }
+
+        self.compile_program(program)
+    }
+
+    /// Compiles a type checked program.
+    pub(crate) fn compile_program(&mut self, program: Program) -> Result<CompiledProgram, TealError> {
+        if self.dumps.contains(&Emit::Wat) {
+            self.write_debug(&generate_assembly(program.clone()));
+        }
+
+        let mut host_functions = vec![];
+        for (ident, signature) in externs(&program) {
+            let host = self.host_functions.get(&ident)
+                .ok_or_else(|| TealError::Link(format!("no host function named '{}' is registered", ident)))?;
+            if host.signature != signature {
+                return Err(TealError::Link(format!(
+                    "'{}' is declared as {} but the host function is {}", ident, signature, host.signature
+                )));
+            }
+            host_functions.push(host.clone());
+        }
+
+        let signatures = signatures(&program);
+        let wasm = generate_wasm(program);
+        let module = if self.limits.is_unlimited() {
+            Module::new(&self.store, wasm).map_err(codegen_error)?
+        } else {
+            // The module counts what it uses, the limits are set when it's run.
+            let mut compiler = Cranelift::default();
+            compiler.push_middleware(Arc::new(Instrumentation::for_module(&wasm).map_err(codegen_error)?));
+            if self.limits.fuel.is_some() {
+                compiler.push_middleware(Arc::new(Metering::new(0, fuel_cost)));
+            }
+            Module::new(&Store::new(&Universal::new(compiler).engine()), wasm).map_err(codegen_error)?
+        };
+
+        Ok(CompiledProgram {
+            module,
+            signatures: Arc::new(signatures),
+            host_functions: Arc::new(host_functions),
+            limits: self.limits,
+        })
+    }
+
+    fn write_debug(&mut self, dump: &str) {
+        // Debug output is best effort.
+        let _ = self.debug.write_all(dump.as_bytes());
+    }
+}
+
+/// A compiled program, which can be instantiated any number of times and
+/// shared between threads.
+#[derive(Clone)]
+pub struct CompiledProgram {
+    module: Module,
+    signatures: Arc<HashMap<Identifier, Signature>>,
+    /// The host functions the program declares.
+    host_functions: Arc<Vec<HostFunction>>,
+    limits: Limits,
+}
+
+impl CompiledProgram {
+    /// Creates an instance of the program with memory and globals of its
+    /// own. What it prints is collected, see `Instance::take_output`.
+    pub fn instantiate(&self) -> Result<Instance, TealError> {
+        let buffer = Buffer::new();
+        let mut instance = self.instantiate_with(buffer.clone())?;
+        instance.buffer = Some(buffer);
+        Ok(instance)
+    }
+
+    /// Creates an instance of the program that prints to `output`. Fails with
+    /// `Limit::Memory` if the limits don't leave room for the memory the
+    /// program starts with.
+    pub fn instantiate_with(&self, output: impl Output + 'static) -> Result<Instance, TealError> {
+        if let Some(pages) = self.limits.memory_pages {
+            if self.module.exports().memories().any(|memory| memory.ty().minimum.0 > pages) {
+                return Err(TealError::Limit(Limit::Memory));
+            }
+        }
+
+        let output: SharedOutput = Arc::new(Mutex::new(output));
+        let store = self.module.store();
+        let mut host = Exports::new();
+        for function in self.host_functions.iter() {
+            host.insert(function.name.clone(), function.to_import(store));
+        }
+
+        let mut imports = ImportObject::new();
+        imports.register(RUNTIME_MODULE, runtime_imports(store, &output));
+        imports.register(HOST_MODULE, host);
+        let instance = wasmer::Instance::new(&self.module, &imports).map_err(codegen_error)?;
+        Ok(Instance { instance, signatures: self.signatures.clone(), output, buffer: None, limits: self.limits })
+    }
+
+    /// The signature of the function `name`, if the program has one.
+    pub fn signature(&self, name: &str) -> Option<&Signature> {
+        self.signatures.get(name)
+    }
+}
+
+/// A running instance of a compiled program.
+pub struct Instance {
+    instance: wasmer::Instance,
+    signatures: Arc<HashMap<Identifier, Signature>>,
+    output: SharedOutput,
+    /// What the program prints, if it was instantiated without an output.
+    buffer: Option<Buffer>,
+    limits: Limits,
+}
+
+impl Instance {
+    /// Runs the top level statements of the program, then its `main` if it
+    /// has one. Globals hold zeroes until the program has run.
+    pub fn run(&self) -> Result<(), TealError> {
+        let start = self.instance.exports.get_function("_start").map_err(codegen_error)?;
+        self.reset_limits()?;
+        let result = start.call(&[]);
+        self.output.lock().unwrap().flush();
+        result.map_err(|e| self.runtime_error(e))?;
+        Ok(())
+    }
+
+    /// Calls the function `name`. Numbers are widened to the types of its
+    /// parameters, a function without a result returns `Value::Unit`.
+    pub fn call(&self, name: &str, args: &[Value]) -> Result<Value, TealError> {
+        let signature = self.signatures.get(name)
+            .ok_or_else(|| TealError::Call(format!("no function named '{}'", name)))?;
+        if args.len() != signature.params.len() {
+            return Err(TealError::Call(format!(
+                "'{}' takes {} arguments but {} were given", name, signature.params.len(), args.len()
+            )));
+        }
+
+        self.reset_limits()?;
+        let mut params = vec![];
+        for (arg, &ty) in args.iter().zip(&signature.params) {
+            if !arg.ty().widens_to(ty) {
+                return Err(TealError::Call(format!("'{}' takes {} but {} was given", name, ty, arg.ty())));
+            }
+            let alloc = self.instance.exports.get_native_function("__alloc").map_err(codegen_error)?;
+            let param = wasm_value(self.memory()?, &alloc, arg.clone().convert(ty)).map_err(|e| self.runtime_error(e))?;
+            params.push(param);
+        }
+
+        let function = self.instance.exports.get_function(&function_export(name)).map_err(codegen_error)?;
+        let results = function.call(&params);
+        self.output.lock().unwrap().flush();
+        let results = results.map_err(|e| self.runtime_error(e))?;
+        match results.first() {
+            Some(result) => Ok(teal_value(self.memory()?, result.clone(), signature.ret)),
+            None => Ok(Value::Unit),
+        }
+    }
+
+    /// Takes what the program printed so far, one entry per `print`. It's
+    /// always empty for an instance created with an output of its own.
+    pub fn take_output(&self) -> Vec<String> {
+        self.buffer.as_ref().map(Buffer::take).unwrap_or_default()
+    }
+
+    /// The value of the global `unique`, by its name after resolution.
+    pub(crate) fn global(&self, unique: &str, ty: Type) -> Result<Value, TealError> {
+        let global = self.instance.exports.get_global(&global_export(unique)).map_err(codegen_error)?;
+        Ok(teal_value(self.memory()?, global.get(), ty))
+    }
+
+    /// Gives the program the fuel and the call depth of its limits again.
+    fn reset_limits(&self) -> Result<(), TealError> {
+        // Only programs compiled with limits count what they use.
+        if self.limits.is_unlimited() {
+            return Ok(());
+        }
+
+        let Limits { fuel, memory_pages, call_depth } = self.limits;
+        // The module compares them unsigned, so no limit is all ones.
+        let values = [
+            (MAX_PAGES, wasmer::Value::I32(memory_pages.unwrap_or(WASM_MAX_PAGES) as i32)),
+            (MAX_DEPTH, wasmer::Value::I32(call_depth.map_or(-1, |depth| depth as i32))),
+            (DEPTH, wasmer::Value::I32(0)),
+            (EXHAUSTED, wasmer::Value::I32(0)),
+        ];
+
+        for (name, value) in values {
+            let global = self.instance.exports.get_global(name).map_err(codegen_error)?;
+            global.set(value).map_err(codegen_error)?;
+        }
+        if let Some(fuel) = fuel {
+            set_remaining_points(&self.instance, fuel);
+        }
+        Ok(())
+    }
+
+    /// The limit the program ran into, if it stopped because of one.
+    fn runtime_error(&self, error: RuntimeError) -> TealError {
+        let limit = match self.instance.exports.get_global(EXHAUSTED).map(|global| global.get()) {
+            Ok(wasmer::Value::I32(code)) => Limit::from_code(code),
+            _ => None,
+        };
+        let out_of_fuel = || {
+            self.limits.fuel.is_some() && get_remaining_points(&self.instance) == MeteringPoints::Exhausted
+        };
+        let limit = limit.or_else(|| out_of_fuel().then_some(Limit::Fuel));
+        limit.map_or_else(|| crate::runtime_error(error), TealError::Limit)
+    }
+
+    fn memory(&self) -> Result<&Memory, TealError> {
+        self.instance.exports.get_memory("memory").map_err(codegen_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use crate::codegen::Signature;
+    use crate::engine::{CompiledProgram, Engine, Instance};
+    use crate::error::TealError;
+    use crate::interp::value::Value;
+    use crate::limits::{Limit, Limits, DEPTH};
+    use crate::output::{Callback, Output};
+    use crate::syntax::ast::Type;
+    use crate::Emit;
+
+    const SOURCE: &str = "\
+let greeting = \"Hello\";
+fun greet(name: string) -> string { \"$greeting, $name!\" }
+fun half(n: f64) -> f64 { n / 2 }
+fun count() { print 1; }
+let calls = 0;
+fun bump() -> i32 {
+    calls = calls + 1;
+    calls
+}
+";
+
+    #[test]
+    fn call_functions() {
+        let program = Engine::new().compile(SOURCE).unwrap();
+        let instance = program.instantiate().unwrap();
+        instance.run().unwrap();
+
+        assert_eq!(instance.call("greet", &[Value::Str("Teal".into())]).unwrap(), Value::Str("Hello, Teal!".into()));
+        assert_eq!(instance.call("half", &[Value::I32(3)]).unwrap(), Value::F64(1.5));
+        assert_eq!(instance.call("count", &[]).unwrap(), Value::Unit);
+        assert_eq!(instance.take_output(), vec!["1"]);
+    }
+
+    #[test]
+    fn instantiate_many_times() {
+        let program = Engine::new().compile(SOURCE).unwrap();
+
+        let first = program.instantiate().unwrap();
+        let second = program.instantiate().unwrap();
+        assert_eq!(first.call("bump", &[]).unwrap(), Value::I32(1));
+        assert_eq!(first.call("bump", &[]).unwrap(), Value::I32(2));
+        assert_eq!(second.call("bump", &[]).unwrap(), Value::I32(1));
+    }
+
+    #[test]
+    fn instantiate_on_other_threads() {
+        let program = Engine::new().compile(SOURCE).unwrap();
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let program = program.clone();
+                std::thread::spawn(move || {
+                    let instance = program.instantiate().unwrap();
+                    assert_eq!(instance.call("bump", &[]).unwrap(), Value::I32(1));
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn reject_bad_calls() {
+        let instance = Engine::new().compile(SOURCE).unwrap().instantiate().unwrap();
+
+        let message = |result: Result<Value, TealError>| match result {
+            Err(TealError::Call(message)) => message,
+            result => panic!("expected a call error, got {:?}", result),
+        };
+        assert_eq!(message(instance.call("wave", &[])), "no function named 'wave'");
+        assert_eq!(message(instance.call("half", &[])), "'half' takes 1 arguments but 0 were given");
+        assert_eq!(message(instance.call("half", &[Value::Bool(true)])), "'half' takes f64 but bool was given");
+    }
+
+    /// A writer the test can read back.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn write_dumps_and_diagnostics() {
+        let debug = Shared::default();
+        let diagnostics = Shared::default();
+        let mut engine = Engine::new();
+        engine.dump(Emit::Wat)
+            .debug_output(debug.clone())
+            .diagnostics_output(diagnostics.clone(), crate::Style::Plain);
+
+        engine.compile("print 1;").unwrap();
+        assert!(debug.contents().starts_with("(module\n"));
+
+        assert!(engine.compile("print x;").is_err());
+        assert!(diagnostics.contents().contains("undefined variable 'x'"));
+    }
+
+    /// Records how each value was written.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Output for Recorder {
+        fn write_int(&mut self, n: i64) {
+            self.0.lock().unwrap().push(format!("int {}", n));
+        }
+
+        fn write_float(&mut self, n: f64) {
+            self.0.lock().unwrap().push(format!("float {}", n));
+        }
+
+        fn write_string(&mut self, s: &str) {
+            self.0.lock().unwrap().push(format!("string {}", s));
+        }
+
+        fn flush(&mut self) {
+            self.0.lock().unwrap().push("flush".to_string());
+        }
+    }
+
+    #[test]
+    fn print_to_outputs() {
+        let source = "print 1;\nprint true;\nlet big: i64 = 5000000000;\nprint big;\nprint 1.5;\nprint \"hi\";";
+        let program = Engine::new().compile(source).unwrap();
+
+        let recorder = Recorder::default();
+        let instance = program.instantiate_with(recorder.clone()).unwrap();
+        instance.run().unwrap();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![
+            "int 1", "int 1", "int 5000000000", "float 1.5", "string hi", "flush"
+        ]);
+        assert_eq!(instance.take_output(), Vec::<String>::new());
+
+        let lines = Arc::new(Mutex::new(vec![]));
+        let callback = {
+            let lines = lines.clone();
+            Callback(move |line: &str| lines.lock().unwrap().push(line.to_string()))
+        };
+        program.instantiate_with(callback).unwrap().run().unwrap();
+        assert_eq!(*lines.lock().unwrap(), vec!["1", "1", "5000000000", "1.5", "hi"]);
+    }
+
+    fn limited(limits: Limits, source: &str) -> Instance {
+        let mut engine = Engine::new();
+        engine.limits(limits);
+        engine.compile(source).unwrap().instantiate().unwrap()
+    }
+
+    fn limit(result: Result<impl std::fmt::Debug, TealError>) -> Limit {
+        match result {
+            Err(TealError::Limit(limit)) => limit,
+            result => panic!("expected a limit error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn stop_at_limits() {
+        let fuel = Limits { fuel: Some(10_000), ..Limits::default() };
+        assert_eq!(limit(limited(fuel, "while true { }").run()), Limit::Fuel);
+
+        let depth = Limits { call_depth: Some(100), ..Limits::default() };
+        let source = "fun down(n: i32) -> i32 { if n == 0 { return 0; } down(n - 1) }";
+        let instance = limited(depth, source);
+        assert_eq!(instance.call("down", &[Value::I32(50)]).unwrap(), Value::I32(0));
+        assert_eq!(limit(instance.call("down", &[Value::I32(200)])), Limit::CallDepth);
+
+        let memory = Limits { memory_pages: Some(4), ..Limits::default() };
+        let source = "let s = \"0123456789abcdef\";\nwhile true { s = \"$s$s\"; }";
+        assert_eq!(limit(limited(memory, source).run()), Limit::Memory);
+    }
+
+    #[test]
+    fn limits_apply_to_each_call() {
+        let limits = Limits { fuel: Some(1_000), call_depth: Some(10), ..Limits::default() };
+        let source = "fun down(n: i32) -> i32 { if n == 0 { return 0; } down(n - 1) }";
+        let instance = limited(limits, source);
+
+        assert_eq!(limit(instance.call("down", &[Value::I32(20)])), Limit::CallDepth);
+        for _ in 0..10 {
+            assert_eq!(instance.call("down", &[Value::I32(5)]).unwrap(), Value::I32(0));
+        }
+        assert_eq!(Engine::new().compile(source).unwrap().instantiate().unwrap()
+            .call("down", &[Value::I32(5000)]).unwrap(), Value::I32(0));
+    }
+
+    #[test]
+    fn only_instrument_limited_programs() {
+        let source = "let a = 1;\nlet b = 2.5;\nprint a;";
+        let instrumented = |instance: &Instance| instance.instance.exports.get_global(DEPTH).is_ok();
+
+        assert!(!instrumented(&Engine::new().compile(source).unwrap().instantiate().unwrap()));
+        let instance = limited(Limits { fuel: Some(1_000), ..Limits::default() }, source);
+        assert!(instrumented(&instance));
+        instance.run().unwrap();
+        assert_eq!(instance.take_output(), vec!["1"]);
+    }
+
+    #[test]
+    fn reject_memory_limits_below_the_start() {
+        let mut engine = Engine::new();
+        engine.limits(Limits { memory_pages: Some(0), ..Limits::default() });
+        assert!(matches!(
+            engine.compile("print 1;").unwrap().instantiate(),
+            Err(TealError::Limit(Limit::Memory))
+        ));
+
+        engine.limits(Limits { memory_pages: Some(1), ..Limits::default() });
+        engine.compile("print 1;").unwrap().instantiate().unwrap().run().unwrap();
+    }
+
+    fn host_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.register("repeat", Signature::new(vec![Type::String, Type::I32], Type::String), |args| {
+            match args {
+                [Value::Str(s), Value::I32(n)] if *n >= 0 => Ok(Value::Str(s.repeat(*n as usize).into())),
+                _ => Err("can't repeat a negative number of times".to_string()),
+            }
+        });
+        engine.register("now", Signature::new(vec![], Type::I32), |_| Ok(Value::I32(1234)));
+        engine
+    }
+
+    #[test]
+    fn call_host_functions() {
+        let source = "\
+extern fun repeat(s: string, n: i32) -> string;
+extern fun now() -> i32;
+print repeat(\"ab\", 3);
+print now() + 1;
+";
+        let instance = host_engine().compile(source).unwrap().instantiate().unwrap();
+        instance.run().unwrap();
+        assert_eq!(instance.take_output(), vec!["ababab", "1235"]);
+    }
+
+    #[test]
+    fn link_host_functions() {
+        let mut engine = host_engine();
+        let message = |result: Result<CompiledProgram, TealError>| match result {
+            Err(TealError::Link(message)) => message,
+            Err(e) => panic!("expected a link error, got {:?}", e),
+            Ok(_) => panic!("expected a link error"),
+        };
+
+        assert_eq!(message(engine.compile("extern fun wave();")), "no host function named 'wave' is registered");
+        assert_eq!(
+            message(engine.compile("extern fun now() -> i64;")),
+            "'now' is declared as () -> i64 but the host function is () -> i32"
+        );
+    }
+
+    #[test]
+    fn keep_host_functions_apart_from_the_runtime() {
+        let mut engine = host_engine();
+        engine.register("log", Signature::new(vec![Type::I32], Type::Unit), |_| Ok(Value::Unit));
+
+        match engine.compile("extern fun log(n: i32);\nprint 5;") {
+            Err(TealError::Resolve(errors)) => {
+                assert_eq!(errors[0].to_string(), "[line 1] Error: 'log' is the name of a runtime function");
+            }
+            Err(e) => panic!("expected a resolve error, got {:?}", e),
+            Ok(_) => panic!("expected a resolve error"),
+        }
+
+        let instance = engine.compile("extern fun now() -> i32;\nprint now();").unwrap().instantiate().unwrap();
+        instance.run().unwrap();
+        assert_eq!(instance.take_output(), vec!["1234"]);
+    }
+
+    #[test]
+    fn host_function_errors_stop_the_program() {
+        let source = "extern fun repeat(s: string, n: i32) -> string;\nprint repeat(\"ab\", -1);";
+        let instance = host_engine().compile(source).unwrap().instantiate().unwrap();
+        match instance.run() {
+            Err(TealError::Runtime(message)) => assert!(
+                message.contains("host function 'repeat' failed: can't repeat a negative number of times"),
+                "{}", message
+            ),
+            result => panic!("expected a runtime error, got {:?}", result),
+        }
+    }
+}
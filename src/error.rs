@@ -0,0 +1,94 @@
+use std::fmt;
+use crate::codegen::runtime::TrapError;
+use crate::limits::Limit;
+use crate::resolve::error::ResolveError;
+use crate::syntax::ParserError;
+use crate::types::error::TypeError;
+
+/// Everything that can go wrong compiling or running a Teal program.
+#[derive(Debug)]
+pub enum TealError {
+    /// Every syntax error the lexer and parser found, in source order.
+    Parser(Vec<ParserError>),
+    /// Every undefined or duplicate name.
+    Resolve(Vec<ResolveError>),
+    Type(TypeError),
+    /// The generated module was rejected by the runtime, a compiler bug.
+    Codegen(String),
+    /// An error the program reported, such as a division by zero.
+    Trap(TrapError),
+    /// The program used more than the engine's limits allow.
+    Limit(Limit),
+    /// Any other failure while running the program.
+    Runtime(String),
+    /// The program declares a host function the engine doesn't provide, or
+    /// with another signature.
+    Link(String),
+    /// The host called a function that doesn't exist or with arguments it
+    /// doesn't take.
+    Call(String),
+}
+
+impl TealError {
+    /// Whether the program failed before it started running.
+    pub fn is_compile_error(&self) -> bool {
+        !matches!(self, TealError::Trap(_) | TealError::Limit(_) | TealError::Runtime(_) | TealError::Call(_))
+    }
+}
+
+impl fmt::Display for TealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TealError::Parser(errors) => {
+                let messages: Vec<String> = errors.iter().map(ParserError::to_string).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            TealError::Resolve(errors) => {
+                let messages: Vec<String> = errors.iter().map(ResolveError::to_string).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            TealError::Type(e) => write!(f, "{}", e),
+            TealError::Codegen(message) => write!(f, "Error: invalid module: {}", message),
+            TealError::Trap(e) => write!(f, "{}", e),
+            TealError::Limit(limit) => write!(f, "Runtime error: {}", limit),
+            TealError::Runtime(message) => write!(f, "Runtime error: {}", message),
+            TealError::Link(message) | TealError::Call(message) => write!(f, "Error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TealError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TealError::Parser(errors) => errors.first().map(|e| e as _),
+            TealError::Resolve(errors) => errors.first().map(|e| e as _),
+            TealError::Type(e) => Some(e),
+            TealError::Trap(e) => Some(e),
+            TealError::Codegen(_) | TealError::Limit(_) | TealError::Runtime(_) | TealError::Link(_) | TealError::Call(_) => None,
+        }
+    }
+}
+
+impl From<Vec<ParserError>> for TealError {
+    fn from(errors: Vec<ParserError>) -> Self {
+        TealError::Parser(errors)
+    }
+}
+
+impl From<Vec<ResolveError>> for TealError {
+    fn from(errors: Vec<ResolveError>) -> Self {
+        TealError::Resolve(errors)
+    }
+}
+
+impl From<TypeError> for TealError {
+    fn from(e: TypeError) -> Self {
+        TealError::Type(e)
+    }
+}
+
+impl From<TrapError> for TealError {
+    fn from(e: TrapError) -> Self {
+        TealError::Trap(e)
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::sync::Arc;
+use wasmer::{Function, FunctionType, LazyInit, Memory, NativeFunc, RuntimeError, Store, WasmerEnv};
+use crate::codegen::Signature;
+use crate::interp::value::Value;
+use crate::syntax::ast::Type;
+use crate::{read_string, write_string};
+
+/// The Rust side of a host function, it's passed arguments of the types of
+/// its signature and returns a value of its result type, or an error
+/// message that stops the program.
+pub type HostFn = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+/// A function registered on an engine, which programs declare with
+/// `extern fun`.
+#[derive(Clone)]
+pub struct HostFunction {
+    pub name: String,
+    pub signature: Signature,
+    pub function: Arc<HostFn>,
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFunction({} {})", self.name, self.signature)
+    }
+}
+
+#[derive(WasmerEnv, Clone)]
+struct HostEnv {
+    host: HostFunction,
+    #[wasmer(export)]
+    memory: LazyInit<Memory>,
+    #[wasmer(export(name = "__alloc"))]
+    alloc: LazyInit<NativeFunc<i32, i32>>,
+}
+
+impl HostFunction {
+    /// The function to import, converting values between Teal and wasm.
+    pub fn to_import(&self, store: &Store) -> Function {
+        let params: Vec<wasmer::Type> = self.signature.params.iter().map(|&ty| wasm_type(ty).unwrap()).collect();
+        let results: Vec<wasmer::Type> = wasm_type(self.signature.ret).into_iter().collect();
+        let env = HostEnv { host: self.clone(), memory: LazyInit::new(), alloc: LazyInit::new() };
+
+        Function::new_with_env(store, FunctionType::new(params, results), env, call_host)
+    }
+}
+
+fn call_host(env: &HostEnv, args: &[wasmer::Value]) -> Result<Vec<wasmer::Value>, RuntimeError> {
+    let memory = env.memory_ref().unwrap();
+    let HostFunction { name, signature, function } = &env.host;
+
+    let args: Vec<Value> = args.iter()
+        .zip(&signature.params)
+        .map(|(arg, &ty)| teal_value(memory, arg.clone(), ty))
+        .collect();
+
+    let result = function(&args)
+        .map_err(|message| RuntimeError::new(format!("host function '{}' failed: {}", name, message)))?;
+    if !result.ty().widens_to(signature.ret) {
+        let message = format!("host function '{}' returned {} instead of {}", name, result.ty(), signature.ret);
+        return Err(RuntimeError::new(message));
+    }
+
+    match result.convert(signature.ret) {
+        Value::Unit => Ok(vec![]),
+        value => Ok(vec![wasm_value(memory, env.alloc_ref().unwrap(), value)?]),
+    }
+}
+
+/// The wasm type a value of `ty` is passed as, `None` for unit.
+fn wasm_type(ty: Type) -> Option<wasmer::Type> {
+    match ty {
+        Type::I64 => Some(wasmer::Type::I64),
+        Type::F32 => Some(wasmer::Type::F32),
+        Type::F64 => Some(wasmer::Type::F64),
+        Type::Unit => None,
+        _ => Some(wasmer::Type::I32),
+    }
+}
+
+/// Converts a value to the wasm value it's represented by, strings are
+/// copied into the module's memory.
+pub fn wasm_value(memory: &Memory, alloc: &NativeFunc<i32, i32>, value: Value) -> Result<wasmer::Value, RuntimeError> {
+    Ok(match value {
+        Value::I32(n) => wasmer::Value::I32(n),
+        Value::I64(n) => wasmer::Value::I64(n),
+        Value::F32(n) => wasmer::Value::F32(n),
+        Value::F64(n) => wasmer::Value::F64(n),
+        Value::Bool(b) => wasmer::Value::I32(b as i32),
+        Value::Str(s) => wasmer::Value::I32(write_string(memory, alloc, &s)?),
+        Value::Unit => unreachable!("unit values aren't passed to wasm"),
+    })
+}
+
+/// Converts a wasm value the module produced for a value of type `ty`.
+pub fn teal_value(memory: &Memory, value: wasmer::Value, ty: Type) -> Value {
+    match (value, ty) {
+        (wasmer::Value::I32(n), Type::Bool) => Value::Bool(n != 0),
+        (wasmer::Value::I32(ptr), Type::String) => Value::Str(read_value_string(memory, ptr).into()),
+        (wasmer::Value::I32(n), _) => Value::I32(n),
+        (wasmer::Value::I64(n), _) => Value::I64(n),
+        (wasmer::Value::F32(n), _) => Value::F32(n),
+        (wasmer::Value::F64(n), _) => Value::F64(n),
+        (value, _) => panic!("expected a value of type {} but found {:?}", ty, value),
+    }
+}
+
+/// Reads a string value, the address of its length followed by its bytes.
+fn read_value_string(memory: &Memory, ptr: i32) -> String {
+    let view = memory.view::<u8>();
+    let mut len = [0; 4];
+    for (i, b) in len.iter_mut().enumerate() {
+        *b = view[ptr as usize + i].get();
+    }
+
+    read_string(memory, ptr + 4, i32::from_le_bytes(len))
+}
@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use crate::codegen::runtime::{Trap, TrapError};
+use crate::error::TealError;
+use crate::interp::value::Value;
+use crate::output::Output;
+use crate::syntax::Span;
+use crate::syntax::ast::{BinaryOperator, Expr, ExprKind, Identifier, LiteralExpr, Param, Program, Type, UnaryOperator};
+
+pub mod value;
+
+/// How many calls can be nested before the interpreter stops with the error
+/// the generated code reports when it runs out of stack. Calls don't take
+/// any of the host's stack, this is about as deep as the module gets with
+/// the stack of a main thread.
+const MAX_CALL_DEPTH: usize = 50_000;
+
+/// Runs a resolved and type checked program by walking its tree, printing to
+/// `output` the same values as the generated module.
+pub fn interpret(program: &Program, output: &mut dyn Output) -> Result<(), TealError> {
+    let mut interpreter = Interpreter::new(output);
+    interpreter.declare_functions(program);
+
+    let result = interpreter.run(program);
+    interpreter.output.flush();
+    result
+}
+
+/// A step of evaluation. Expressions are evaluated by tasks that push their
+/// value, the ones evaluating a part of an expression run before the task
+/// that uses its value.
+enum Task<'a> {
+    Eval(&'a Expr),
+    Push(Value),
+    /// Drops the value of a statement.
+    Discard,
+    Convert(Type),
+    Unary(UnaryOperator),
+    /// Applies the operator to the two values on top, the left one below.
+    Binary(BinaryOperator, Span),
+    /// Evaluates the right operand of `and` or `or`, unless the left one on
+    /// top decides the result already.
+    Logical(BinaryOperator, &'a Expr),
+    Declare(&'a Identifier),
+    Set(&'a Identifier),
+    Print,
+    /// Evaluates the branch the condition on top picks, if there's one.
+    Branch(&'a Expr, Option<&'a Expr>),
+    /// Runs an iteration of the loop if the condition on top holds.
+    Test(Loop<'a>),
+    /// Starts a `for` loop with the start and end of its range on top.
+    StartFor(&'a Identifier, &'a Expr),
+    /// Joins that many parts of an interpolated string.
+    Interpolate(usize),
+    /// Calls the function with its arguments on top.
+    Call(&'a str),
+    /// Returns the value on top from the function being called.
+    Return,
+    /// The end of an iteration of a loop, `break` and `continue` unwind to
+    /// it. There were `height` values when the iteration started.
+    Iteration(Loop<'a>, usize),
+    /// The end of a call, `return` unwinds to it. The caller's result type
+    /// is `ret` and there were `height` values when the call started.
+    Returned { ret: Type, height: usize },
+}
+
+#[derive(Clone, Copy)]
+enum Loop<'a> {
+    While { condition: &'a Expr, body: &'a Expr },
+    For { ident: &'a Identifier, end: i32, body: &'a Expr },
+}
+
+struct Function<'a> {
+    params: &'a [Param],
+    ret: Type,
+    /// `None` for functions the host provides.
+    body: Option<&'a [Expr]>,
+}
+
+struct Interpreter<'a> {
+    globals: HashMap<Identifier, Value>,
+    /// The locals of the functions being called, innermost last. Empty
+    /// while running top level code.
+    frames: Vec<HashMap<Identifier, Value>>,
+    functions: HashMap<&'a str, Function<'a>>,
+    /// The result type of the function being called.
+    ret: Type,
+    /// What's left to do, the next task last. Calls are tasks too, so how
+    /// deep they nest doesn't depend on the host's stack.
+    tasks: Vec<Task<'a>>,
+    /// The values of the expressions evaluated so far that haven't been
+    /// used yet.
+    values: Vec<Value>,
+    output: &'a mut dyn Output,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(output: &'a mut dyn Output) -> Self {
+        Interpreter {
+            globals: HashMap::new(),
+            frames: vec![],
+            functions: HashMap::new(),
+            ret: Type::Unit,
+            tasks: vec![],
+            values: vec![],
+            output,
+        }
+    }
+
+    fn run(&mut self, program: &'a Program) -> Result<(), TealError> {
+        let mut tasks: Vec<Task> = program.iter().flat_map(|expr| [Task::Eval(expr), Task::Discard]).collect();
+        // A user defined main runs after the top level statements.
+        if self.functions.contains_key("main") {
+            tasks.extend([Task::Call("main"), Task::Discard]);
+        }
+        self.schedule(tasks);
+
+        while let Some(task) = self.tasks.pop() {
+            self.step(task)?;
+        }
+        Ok(())
+    }
+
+    /// Functions can be called before they are declared, like in the
+    /// generated module.
+    fn declare_functions(&mut self, exprs: &'a [Expr]) {
+        for expr in exprs {
+            match &expr.kind {
+                ExprKind::Fun { ident, params, ret, body } => {
+                    self.functions.insert(ident.as_str(), Function { params, ret: *ret, body: Some(body) });
+                    self.declare_functions(body);
+                }
+                ExprKind::Extern { ident, params, ret } => {
+                    self.functions.insert(ident.as_str(), Function { params, ret: *ret, body: None });
+                }
+                ExprKind::Block(exprs) => self.declare_functions(exprs),
+                ExprKind::IfElse { then, else_, .. } => {
+                    self.declare_functions(std::slice::from_ref(then.as_ref()));
+                    if let Some(else_) = else_ {
+                        self.declare_functions(std::slice::from_ref(else_.as_ref()));
+                    }
+                }
+                ExprKind::While { body, .. } | ExprKind::For { body, .. } => {
+                    self.declare_functions(std::slice::from_ref(body.as_ref()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs `tasks` next, in order.
+    fn schedule(&mut self, tasks: Vec<Task<'a>>) {
+        self.tasks.extend(tasks.into_iter().rev());
+    }
+
+    fn push(&mut self, value: Value) {
+        self.values.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.values.pop().expect("every expression has a value")
+    }
+
+    fn step(&mut self, task: Task<'a>) -> Result<(), TealError> {
+        match task {
+            Task::Eval(expr) => self.eval(expr),
+            Task::Push(value) => self.push(value),
+            Task::Discard => {
+                self.pop();
+            }
+            Task::Convert(ty) => {
+                let value = self.pop().convert(ty);
+                self.push(value);
+            }
+            Task::Unary(op) => {
+                let value = match (op, self.pop()) {
+                    (UnaryOperator::Negate, Value::I32(n)) => Value::I32(n.wrapping_neg()),
+                    (UnaryOperator::Negate, Value::I64(n)) => Value::I64(n.wrapping_neg()),
+                    (UnaryOperator::Negate, Value::F32(n)) => Value::F32(-n),
+                    (UnaryOperator::Negate, Value::F64(n)) => Value::F64(-n),
+                    (UnaryOperator::Not, Value::Bool(b)) => Value::Bool(!b),
+                    (op, value) => unreachable!("the checker rejects {:?} {:?}", op, value),
+                };
+                self.push(value);
+            }
+            Task::Binary(op, span) => {
+                let right = self.pop();
+                let left = self.pop();
+                let value = binary(op, left, right, span)?;
+                self.push(value);
+            }
+            Task::Logical(op, right) => match (op, self.pop().as_bool()) {
+                (BinaryOperator::And, false) => self.push(Value::Bool(false)),
+                (BinaryOperator::Or, true) => self.push(Value::Bool(true)),
+                _ => self.schedule(vec![Task::Eval(right)]),
+            },
+            Task::Declare(ident) => {
+                let value = self.pop();
+                self.declare(ident, value);
+                self.push(Value::Unit);
+            }
+            Task::Set(ident) => {
+                let value = self.pop();
+                self.set(ident, value);
+                self.push(Value::Unit);
+            }
+            Task::Print => {
+                // Written like the module writes it.
+                match self.pop() {
+                    Value::I32(n) => self.output.write_int(n as i64),
+                    Value::Bool(b) => self.output.write_int(b as i64),
+                    Value::I64(n) => self.output.write_int(n),
+                    Value::F64(n) => self.output.write_float(n),
+                    value => self.output.write_string(&value.to_string()),
+                }
+                self.push(Value::Unit);
+            }
+            Task::Branch(then, else_) => {
+                let branch = if self.pop().as_bool() { Some(then) } else { else_ };
+                match branch {
+                    Some(branch) => self.schedule(vec![Task::Eval(branch), Task::Discard, Task::Push(Value::Unit)]),
+                    None => self.push(Value::Unit),
+                }
+            }
+            Task::Test(loop_) => {
+                if self.pop().as_bool() {
+                    self.iterate(loop_);
+                } else {
+                    self.push(Value::Unit);
+                }
+            }
+            Task::StartFor(ident, body) => {
+                // The end is evaluated once, before the first iteration.
+                let end = self.pop().as_i32();
+                let start = self.pop().as_i32();
+                self.declare(ident, Value::I32(start));
+                self.next_iteration(Loop::For { ident, end, body });
+            }
+            Task::Interpolate(parts) => {
+                let parts = self.values.split_off(self.values.len() - parts);
+                let s: String = parts.iter().map(Value::to_string).collect();
+                self.push(Value::Str(s.into()));
+            }
+            Task::Call(ident) => self.call(ident)?,
+            Task::Return => {
+                let value = self.pop();
+                self.return_(value);
+            }
+            Task::Iteration(loop_, _) => self.after_iteration(loop_),
+            Task::Returned { ret, .. } => {
+                self.frames.pop();
+                self.ret = ret;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Schedules the evaluation of `expr`, or pushes its value right away.
+    fn eval(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Block(exprs) => {
+                let statements = exprs.iter().flat_map(|e| [Task::Eval(e), Task::Discard]);
+                self.schedule(statements.chain([Task::Push(Value::Unit)]).collect());
+            }
+            ExprKind::Binary { left, op: op @ (BinaryOperator::And | BinaryOperator::Or), right } => {
+                // `and` and `or` only evaluate the right operand when the
+                // left one doesn't decide the result already.
+                self.schedule(vec![Task::Eval(left), Task::Logical(*op, right)]);
+            }
+            ExprKind::Binary { left, op, right } => {
+                // Both operands are converted to the wider of their types.
+                let operand = match op {
+                    BinaryOperator::Add
+                    | BinaryOperator::Subtract
+                    | BinaryOperator::Multiply
+                    | BinaryOperator::Divide
+                    | BinaryOperator::Remainder => expr.ty,
+                    _ => left.ty.wider(right.ty),
+                };
+
+                self.schedule(vec![
+                    Task::Eval(left),
+                    Task::Convert(operand),
+                    Task::Eval(right),
+                    Task::Convert(operand),
+                    Task::Binary(*op, expr.span),
+                ]);
+            }
+            ExprKind::Unary { op, expr } => self.schedule(vec![Task::Eval(expr), Task::Unary(*op)]),
+            ExprKind::LetAssign { ident, ty, initializer } => {
+                self.schedule(vec![Task::Eval(initializer), Task::Convert(*ty), Task::Declare(ident)]);
+            }
+            ExprKind::LetGet { ident } => {
+                let value = self.get(ident).unwrap_or_else(|| Value::zero(expr.ty));
+                self.push(value);
+            }
+            ExprKind::LetSet { ident, expr } => self.schedule(vec![Task::Eval(expr), Task::Set(ident)]),
+            ExprKind::Print { value } => self.schedule(vec![Task::Eval(value), Task::Print]),
+            ExprKind::IfElse { condition, then, else_ } => {
+                self.schedule(vec![Task::Eval(condition), Task::Branch(then, else_.as_deref())]);
+            }
+            // Declared up front.
+            ExprKind::Fun { .. } | ExprKind::Extern { .. } => self.push(Value::Unit),
+            ExprKind::Call { callee, args } => {
+                let ident = match &callee.kind {
+                    ExprKind::LetGet { ident } => ident.as_str(),
+                    _ => unreachable!("the checker only allows calling functions by name"),
+                };
+
+                let params = self.functions[ident].params;
+                let args = args.iter().zip(params).flat_map(|(arg, param)| [Task::Eval(arg), Task::Convert(param.ty)]);
+                self.schedule(args.chain([Task::Call(ident)]).collect());
+            }
+            ExprKind::Return { value: Some(value) } => {
+                self.schedule(vec![Task::Eval(value), Task::Convert(self.ret), Task::Return]);
+            }
+            ExprKind::Return { value: None } => self.return_(Value::Unit),
+            ExprKind::While { condition, body } => self.next_iteration(Loop::While { condition, body }),
+            ExprKind::For { ident, start, end, body } => {
+                self.schedule(vec![Task::Eval(start), Task::Eval(end), Task::StartFor(ident, body)]);
+            }
+            ExprKind::Break => {
+                self.unwind_loop();
+                self.push(Value::Unit);
+            }
+            ExprKind::Continue => {
+                let loop_ = self.unwind_loop();
+                self.after_iteration(loop_);
+            }
+            ExprKind::Interpolation(parts) => {
+                let tasks = parts.iter().map(Task::Eval).chain([Task::Interpolate(parts.len())]);
+                self.schedule(tasks.collect());
+            }
+            ExprKind::Literal(literal) => {
+                let value = match literal {
+                    LiteralExpr::Number(n) => match expr.ty {
+                        Type::I64 => Value::I64(*n),
+                        Type::F32 => Value::F32(*n as f32),
+                        Type::F64 => Value::F64(*n as f64),
+                        _ => Value::I32(*n as i32),
+                    },
+                    LiteralExpr::Float(n) if expr.ty == Type::F32 => Value::F32(*n as f32),
+                    LiteralExpr::Float(n) => Value::F64(*n),
+                    LiteralExpr::String(s) => Value::Str(s.as_str().into()),
+                    LiteralExpr::True => Value::Bool(true),
+                    LiteralExpr::False => Value::Bool(false),
+                };
+                self.push(value);
+            }
+        }
+    }
+
+    /// Checks the condition of a loop, before its first iteration and after
+    /// every other.
+    fn next_iteration(&mut self, loop_: Loop<'a>) {
+        match loop_ {
+            Loop::While { condition, .. } => self.schedule(vec![Task::Eval(condition), Task::Test(loop_)]),
+            Loop::For { ident, end, .. } => {
+                if self.get(ident).unwrap().as_i32() < end {
+                    self.iterate(loop_);
+                } else {
+                    self.push(Value::Unit);
+                }
+            }
+        }
+    }
+
+    fn iterate(&mut self, loop_: Loop<'a>) {
+        let body = match loop_ {
+            Loop::While { body, .. } | Loop::For { body, .. } => body,
+        };
+        let height = self.values.len();
+        self.schedule(vec![Task::Eval(body), Task::Discard, Task::Iteration(loop_, height)]);
+    }
+
+    fn after_iteration(&mut self, loop_: Loop<'a>) {
+        if let Loop::For { ident, .. } = loop_ {
+            let i = self.get(ident).unwrap().as_i32();
+            self.set(ident, Value::I32(i.wrapping_add(1)));
+        }
+        self.next_iteration(loop_);
+    }
+
+    /// Drops what's left of the iteration of the innermost loop.
+    fn unwind_loop(&mut self) -> Loop<'a> {
+        loop {
+            match self.tasks.pop() {
+                Some(Task::Iteration(loop_, height)) => {
+                    self.values.truncate(height);
+                    return loop_;
+                }
+                Some(_) => {}
+                None => unreachable!("the checker rejects break and continue outside of loops"),
+            }
+        }
+    }
+
+    /// Drops what's left of the function being called and returns `value`
+    /// from it.
+    fn return_(&mut self, value: Value) {
+        loop {
+            match self.tasks.pop() {
+                Some(Task::Returned { ret, height }) => {
+                    self.values.truncate(height);
+                    self.push(value);
+                    self.frames.pop();
+                    self.ret = ret;
+                    return;
+                }
+                Some(_) => {}
+                None => unreachable!("the checker rejects return outside of functions"),
+            }
+        }
+    }
+
+    fn call(&mut self, ident: &'a str) -> Result<(), TealError> {
+        let function = &self.functions[ident];
+        let (params, ret) = (function.params, function.ret);
+        let body = function.body.ok_or_else(|| {
+            TealError::Runtime(format!("host function '{}' isn't available to the interpreter", ident))
+        })?;
+
+        if self.frames.len() == MAX_CALL_DEPTH {
+            return Err(TealError::Runtime("call stack exhausted".to_string()));
+        }
+
+        let args = self.values.split_off(self.values.len() - params.len());
+        let locals = params.iter().map(|p| p.ident.clone()).zip(args).collect();
+        self.frames.push(locals);
+        let returned = Task::Returned { ret: std::mem::replace(&mut self.ret, ret), height: self.values.len() };
+
+        // The value of a trailing expression is the result.
+        let mut tasks = vec![];
+        match body.split_last() {
+            Some((last, rest)) if ret != Type::Unit && last.is_value() => {
+                tasks.extend(rest.iter().flat_map(|expr| [Task::Eval(expr), Task::Discard]));
+                tasks.extend([Task::Eval(last), Task::Convert(ret)]);
+            }
+            _ => {
+                tasks.extend(body.iter().flat_map(|expr| [Task::Eval(expr), Task::Discard]));
+                tasks.push(Task::Push(Value::Unit));
+            }
+        }
+        tasks.push(returned);
+        self.schedule(tasks);
+
+        Ok(())
+    }
+
+    fn get(&self, ident: &Identifier) -> Option<Value> {
+        self.frames.last()
+            .and_then(|locals| locals.get(ident))
+            .or_else(|| self.globals.get(ident))
+            .cloned()
+    }
+
+    /// Assigns a variable, converting the value to the variable's type.
+    fn set(&mut self, ident: &Identifier, value: Value) {
+        let variable = match self.frames.last_mut() {
+            Some(locals) if locals.contains_key(ident) => locals.get_mut(ident),
+            _ => self.globals.get_mut(ident),
+        };
+
+        match variable {
+            Some(variable) => *variable = value.convert(variable.ty()),
+            None => {
+                self.globals.insert(ident.clone(), value);
+            }
+        }
+    }
+
+    /// Declares a variable in the function being called, or a global in top
+    /// level code.
+    fn declare(&mut self, ident: &Identifier, value: Value) {
+        match self.frames.last_mut() {
+            Some(locals) => locals.insert(ident.clone(), value),
+            None => self.globals.insert(ident.clone(), value),
+        };
+    }
+}
+
+fn binary(op: BinaryOperator, left: Value, right: Value, span: Span) -> Result<Value, TealError> {
+    match (left, right) {
+        (Value::I32(l), Value::I32(r)) => {
+            integer_op(op, l as i64, r as i64, i32::MIN as i64, |n| Value::I32(n as i32), span)
+        }
+        (Value::I64(l), Value::I64(r)) => integer_op(op, l, r, i64::MIN, Value::I64, span),
+        (Value::F32(l), Value::F32(r)) => Ok(float_op(op, l as f64, r as f64, |n| Value::F32(n as f32))),
+        (Value::F64(l), Value::F64(r)) => Ok(float_op(op, l, r, Value::F64)),
+        (Value::Bool(l), Value::Bool(r)) => match op {
+            BinaryOperator::Equal => Ok(Value::Bool(l == r)),
+            BinaryOperator::BangEqual => Ok(Value::Bool(l != r)),
+            _ => unreachable!("the checker rejects {:?} on bools", op),
+        },
+        (left, right) => unreachable!("the checker rejects {:?} {:?} {:?}", left, op, right),
+    }
+}
+
+/// Applies `op` to integers of the type `wrap` makes values of, `min` is the
+/// smallest value of that type. Arithmetic wraps around like in wasm.
+fn integer_op(op: BinaryOperator, l: i64, r: i64, min: i64, wrap: impl Fn(i64) -> Value, span: Span) -> Result<Value, TealError> {
+    let value = match op {
+        BinaryOperator::Add => wrap(l.wrapping_add(r)),
+        BinaryOperator::Subtract => wrap(l.wrapping_sub(r)),
+        BinaryOperator::Multiply => wrap(l.wrapping_mul(r)),
+        BinaryOperator::Divide | BinaryOperator::Remainder if r == 0 => {
+            return Err(TealError::Trap(TrapError { trap: Trap::DivisionByZero, span }));
+        }
+        BinaryOperator::Divide if l == min && r == -1 => {
+            return Err(TealError::Runtime("integer overflow".to_string()));
+        }
+        BinaryOperator::Divide => wrap(l / r),
+        BinaryOperator::Remainder => wrap(l.wrapping_rem(r)),
+        _ => Value::Bool(compare(op, l, r)),
+    };
+
+    Ok(value)
+}
+
+fn float_op(op: BinaryOperator, l: f64, r: f64, wrap: impl Fn(f64) -> Value) -> Value {
+    match op {
+        BinaryOperator::Add => wrap(l + r),
+        BinaryOperator::Subtract => wrap(l - r),
+        BinaryOperator::Multiply => wrap(l * r),
+        BinaryOperator::Divide => wrap(l / r),
+        _ => Value::Bool(compare(op, l, r)),
+    }
+}
+
+fn compare<T: PartialOrd>(op: BinaryOperator, l: T, r: T) -> bool {
+    match op {
+        BinaryOperator::Equal => l == r,
+        BinaryOperator::BangEqual => l != r,
+        BinaryOperator::GreaterThan => l > r,
+        BinaryOperator::GreaterThanEqual => l >= r,
+        BinaryOperator::LessThan => l < r,
+        BinaryOperator::LessThanEqual => l <= r,
+        _ => unreachable!("{:?} is not a comparison", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::runtime::{Trap, TrapError};
+    use crate::error::TealError;
+    use crate::front_end;
+    use crate::interp::interpret;
+    use crate::output::Buffer;
+    use crate::syntax::Span;
+
+    fn interpret_source(source: &str) -> Result<Vec<String>, TealError> {
+        let mut output = Buffer::new();
+        interpret(&front_end(source).unwrap(), &mut output)?;
+        Ok(output.take())
+    }
+
+    #[test]
+    fn wrap_integer_arithmetic() {
+        let output = interpret_source("let big = 2147483647;\nprint big + 1;\nprint -(0 - 2147483647 - 1);").unwrap();
+        assert_eq!(output, vec!["-2147483648", "-2147483648"]);
+    }
+
+    #[test]
+    fn report_runaway_recursion_like_the_module() {
+        let source = "fun down(n: i32) -> i32 { down(n + 1) }\nprint down(0);";
+        let error = interpret_source(source).unwrap_err();
+        let module_error = crate::run_with_output(source).unwrap_err();
+
+        assert_eq!(error.to_string(), "Runtime error: call stack exhausted");
+        assert_eq!(error.to_string(), module_error.to_string());
+    }
+
+    #[test]
+    fn nest_calls_without_taking_the_host_stack() {
+        let source = "fun down(n: i32) -> i32 { if n == 0 { return 0; } down(n - 1) }\nprint down(40000);";
+        assert_eq!(interpret_source(source).unwrap(), vec!["0"]);
+    }
+
+    #[test]
+    fn report_division_by_zero() {
+        let error = interpret_source("print 1;\nprint 7 / 0;").unwrap_err();
+        let trap = TrapError { trap: Trap::DivisionByZero, span: Span::new(15, 20, 2, 7) };
+        match error {
+            TealError::Trap(e) => assert_eq!(e, trap),
+            error => panic!("expected a trap, found {}", error),
+        }
+    }
+
+    #[test]
+    fn run_main_last() {
+        let output = interpret_source("fun main() { print 2; }\nprint 1;").unwrap();
+        assert_eq!(output, vec!["1", "2"]);
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::rc::Rc;
+use crate::syntax::ast::Type;
+
+/// A runtime value, one variant per type the checker can infer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Unit,
+}
+
+impl Value {
+    /// The value a variable of type `ty` has before it is assigned, like an
+    /// uninitialized wasm global.
+    pub fn zero(ty: Type) -> Value {
+        match ty {
+            Type::I64 => Value::I64(0),
+            Type::F32 => Value::F32(0.0),
+            Type::F64 => Value::F64(0.0),
+            Type::Bool => Value::Bool(false),
+            Type::String => Value::Str("".into()),
+            Type::Unit => Value::Unit,
+            _ => Value::I32(0),
+        }
+    }
+
+    pub fn ty(&self) -> Type {
+        match self {
+            Value::I32(_) => Type::I32,
+            Value::I64(_) => Type::I64,
+            Value::F32(_) => Type::F32,
+            Value::F64(_) => Type::F64,
+            Value::Bool(_) => Type::Bool,
+            Value::Str(_) => Type::String,
+            Value::Unit => Type::Unit,
+        }
+    }
+
+    /// Converts a number to the wider type `to`, the same conversions the
+    /// generated code does. Other values are returned unchanged.
+    pub fn convert(self, to: Type) -> Value {
+        match (self, to) {
+            (Value::I32(n), Type::I64) => Value::I64(n as i64),
+            (Value::I32(n), Type::F32) => Value::F32(n as f32),
+            (Value::I32(n), Type::F64) => Value::F64(n as f64),
+            (Value::I64(n), Type::F64) => Value::F64(n as f64),
+            (Value::F32(n), Type::F64) => Value::F64(n as f64),
+            (value, _) => value,
+        }
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            value => panic!("expected a bool but found {:?}", value),
+        }
+    }
+
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            Value::I32(n) => *n,
+            value => panic!("expected an i32 but found {:?}", value),
+        }
+    }
+}
+
+/// Formats values the way the wasm host prints them, bools are printed as
+/// the integers they are represented by.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I32(n) => write!(f, "{}", n),
+            Value::I64(n) => write!(f, "{}", n),
+            Value::F32(n) => write!(f, "{}", n),
+            Value::F64(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", *b as i32),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Unit => Ok(()),
+        }
+    }
+}
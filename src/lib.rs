@@ -1,126 +1,241 @@
 mod syntax;
 mod codegen;
+mod resolve;
+mod types;
+mod error;
+mod diagnostics;
+mod interp;
+mod repl;
+mod engine;
+mod host;
+mod limits;
+mod output;
 
 use std::sync::{Arc, Mutex};
 use anyhow::Result;
-use wasmer::{Function, ImportObject, Instance, Memory, MemoryType, MemoryView, Pages, Store};
-use wasmer::Module;
-use wasmer::imports;
+use wasmer::{Exports, Function, LazyInit, Memory, NativeFunc, RuntimeError, Store};
 use wasmer::WasmerEnv;
-use crate::codegen::generate_assembly;
+use crate::codegen::{generate_assembly, generate_wasm};
+use crate::resolve::resolve;
 use crate::syntax::{lex, parse};
+use crate::syntax::ast::{Identifier, Program};
+
+pub use crate::codegen::runtime::{Trap, TrapError};
+pub use crate::diagnostics::{Diagnostic, Label, Level, Style};
+pub use crate::codegen::Signature;
+pub use crate::engine::{CompiledProgram, Engine, Instance};
+pub use crate::error::TealError;
+pub use crate::interp::value::Value;
+pub use crate::limits::{Limit, Limits};
+pub use crate::output::{Buffer, Callback, Output, Stdout};
+pub use crate::repl::Session;
+pub use crate::resolve::error::ResolveError;
+pub use crate::syntax::{ParserError, Span, SyntaxError};
+pub use crate::syntax::ast::Type;
+pub use crate::types::error::TypeError;
+
+/// The output of an instance, shared by the runtime functions it imports.
+type SharedOutput = Arc<Mutex<dyn Output>>;
 
 #[derive(WasmerEnv, Clone)]
 struct Env {
-    output: Arc<Mutex<Vec<String>>>,
+    output: SharedOutput,
+    #[wasmer(export)]
+    memory: LazyInit<Memory>,
+    #[wasmer(export(name = "__alloc"))]
+    alloc: LazyInit<NativeFunc<i32, i32>>,
 }
 
-pub fn run(source: &str) -> Result<()> {
-    // Compile program.
-    let mut tokens = lex(source).unwrap();
-    let ast = parse(&mut tokens).unwrap();
+/// Reads a string the module passed as address and length.
+fn read_string(memory: &Memory, ptr: i32, len: i32) -> String {
+    let view = memory.view::<u8>();
+    let bytes: Vec<u8> = view[ptr as usize..(ptr + len) as usize]
+        .iter()
+        .map(|b| b.get())
+        .collect();
 
-    println!("{:?}", ast);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
 
-    // Generate wasm.
-    let module_wat = generate_assembly(ast);
-    println!("{}", module_wat);
+/// Copies `s` into a fresh allocation in the module's memory and returns the
+/// string value.
+fn write_string(memory: &Memory, alloc: &NativeFunc<i32, i32>, s: &str) -> Result<i32, RuntimeError> {
+    let ptr = alloc.call(4 + s.len() as i32)?;
 
-    // Run wasm.
-    let store = Store::default();
-    let module = Module::new(&store, &module_wat)?;
+    let mut bytes = (s.len() as u32).to_le_bytes().to_vec();
+    bytes.extend(s.bytes());
 
-    // let memory = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
-    let log_func = Function::new_native(&store, log);
+    let view = memory.view::<u8>();
+    for (i, b) in bytes.into_iter().enumerate() {
+        view[ptr as usize + i].set(b);
+    }
 
-    let import_object = imports! {
-        "env" => {
-            "log" => log_func,
-            // "mem" => memory.clone(),
-        }
-    };
+    Ok(ptr)
+}
 
-    // let import_object = get_import_object(store);
+/// A stage of the compiler whose result can be printed, for debugging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Wat,
+}
 
-    let instance = Instance::new(&module, &import_object)?;
+/// Lexes, parses, resolves and type checks `source`.
+fn front_end(source: &str) -> Result<Program, TealError> {
+    let ast = parse_source(source, &[])?;
+    let ast = resolve(ast)?;
+    let ast = types::check(ast)?;
 
-    let main = instance.exports.get_function("main")?;
-    main.call(&[])?;
+    Ok(ast)
+}
 
-    // println!("{:?}", memory);
+/// Lexes and parses `source`, the errors of both are reported together.
+/// `functions` are declared outside of it.
+fn parse_source(source: &str, functions: &[Identifier]) -> Result<Program, TealError> {
+    let (mut tokens, lex_errors) = lex(source);
+    let (ast, parse_errors) = parse(&mut tokens, functions);
+
+    let mut errors: Vec<ParserError> = lex_errors.into_iter().map(ParserError::from).collect();
+    errors.extend(parse_errors);
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| e.span().start);
+        return Err(errors.into());
+    }
 
-    // Without synchronization.
-    // let view: MemoryView<u8> = memory.view();
-    // for byte in view[0x1000 .. 0x1010].iter().map(Cell::get) {
-    //     println!("byte: {}", byte);
-    // }
+    Ok(ast)
+}
 
-// With synchronization.
-//     let atomic_view = view.atomically();
-//     for byte in atomic_view[0x1000 .. 0x1010].iter().map(|atom| atom.load(Ordering::SeqCst)) {
-//         println!("byte: {}", byte);
-//     }
+/// Reports the errors in `source` without generating any code.
+pub fn check(source: &str) -> Result<(), TealError> {
+    front_end(source).map(|_| ())
+}
 
-    let memory = instance.exports.get_memory("memory")?;
+/// Prints the result of the `stage` of compiling `source`. Tokens are printed
+/// even when some are invalid, the syntax tree is printed once it's typed.
+pub fn emit(source: &str, stage: Emit) -> Result<String> {
+    let output = match stage {
+        Emit::Tokens => dump_tokens(source),
+        Emit::Ast => format!("{:#?}\n", front_end(source)?),
+        Emit::Wat => compile_to_wat(source)?,
+    };
 
-    // println!("Querying memory size... {:?}", memory.size());
-    // assert_eq!(memory.size().bytes(), Bytes::from(65536 as usize));
-    // assert_eq!(memory.data_size(), 65536);
+    Ok(output)
+}
 
-    Ok(())
+/// One token per line with its position, invalid tokens included.
+fn dump_tokens(source: &str) -> String {
+    let (tokens, _) = lex(source);
+    tokens.iter()
+        .map(|t| format!("{}:{} {:?} {:?}\n", t.span.line, t.span.column, t.token_type, t.source))
+        .collect()
 }
 
-fn get_import_object(store: Store) -> ImportObject {
-    let memory = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
-    let log_func = Function::new_native(&store, log);
+/// Runs `source` with the tree-walking interpreter instead of compiling it,
+/// returns the same output as `run_with_output`.
+pub fn interpret(source: &str) -> Result<Vec<String>> {
+    let output = Buffer::new();
+    interpret_with(source, output.clone())?;
+    Ok(output.take())
+}
 
-    return imports! {
-        "env" => {
-            "log" => log_func,
-            "mem" => memory,
-        }
-    }
+/// Like `interpret`, but prints to `output`. What was printed before a
+/// runtime error stays printed.
+pub fn interpret_with(source: &str, mut output: impl Output) -> Result<()> {
+    let program = front_end(source)?;
+    Ok(interp::interpret(&program, &mut output)?)
 }
 
-fn log(n: i32) {
-    println!("{}", n);
+/// Compiles `source` to a binary wasm module.
+pub fn compile(source: &str) -> Result<Vec<u8>> {
+    Ok(generate_wasm(front_end(source)?))
 }
 
+/// Compiles `source` to the text format, for debugging.
+pub fn compile_to_wat(source: &str) -> Result<String> {
+    Ok(generate_assembly(front_end(source)?))
+}
+
+/// Compiles and runs `source`, what it prints goes to stdout.
+pub fn run(source: &str) -> Result<()> {
+    let program = Engine::new().compile(source)?;
+    program.instantiate_with(Stdout)?.run()?;
+    Ok(())
+}
+
+/// Compiles and runs `source` and returns what it printed, one entry per
+/// `print`.
 pub fn run_with_output(source: &str) -> Result<Vec<String>> {
-    // Compile program.
-    let mut tokens = lex(source).unwrap();
-    let ast = parse(&mut tokens).unwrap();
+    let output = Buffer::new();
+    let program = Engine::new().compile(source)?;
+    program.instantiate_with(output.clone())?.run()?;
+    Ok(output.take())
+}
 
-    println!("{:?}", ast);
+/// The runtime functions programs import, printing to `output`.
+fn runtime_imports(store: &Store, output: &SharedOutput) -> Exports {
+    let env = Env { output: output.clone(), memory: LazyInit::new(), alloc: LazyInit::new() };
+
+    let mut namespace = Exports::new();
+    namespace.insert("log", Function::new_native_with_env(store, env.clone(), log));
+    namespace.insert("log_i64", Function::new_native_with_env(store, env.clone(), log_i64));
+    namespace.insert("log_str", Function::new_native_with_env(store, env.clone(), log_str));
+    namespace.insert("log_f64", Function::new_native_with_env(store, env.clone(), log_f64));
+    namespace.insert("trap", Function::new_native(store, trap));
+    namespace.insert("i64_to_str", Function::new_native_with_env(store, env.clone(), i64_to_str));
+    namespace.insert("f32_to_str", Function::new_native_with_env(store, env.clone(), f32_to_str));
+    namespace.insert("f64_to_str", Function::new_native_with_env(store, env, f64_to_str));
+
+    namespace
+}
 
-    // Generate wasm.
-    let module_wat = generate_assembly(ast);
-    println!("{}", module_wat);
+/// Unwraps the Teal error a host function raised, other traps are reported
+/// with the runtime's message.
+fn runtime_error(error: RuntimeError) -> TealError {
+    match error.downcast::<TrapError>() {
+        Ok(trap) => TealError::Trap(trap),
+        Err(error) => TealError::Runtime(error.message()),
+    }
+}
+
+/// The runtime rejected the module we generated or couldn't link it.
+fn codegen_error(error: impl std::error::Error) -> TealError {
+    TealError::Codegen(error.to_string())
+}
 
-    // Run wasm.
-    let store = Store::default();
-    let module = Module::new(&store, &module_wat)?;
+/// Aborts the program with the error it reported at the span passed as its
+/// start, end, line and column.
+fn trap(code: i32, start: i32, end: i32, line: i32, column: i32) -> Result<(), RuntimeError> {
+    let trap = Trap::from_code(code).expect("unknown trap code");
+    let span = Span::new(start as usize, end as usize, line as usize, column as usize);
+    Err(RuntimeError::user(Box::new(TrapError { trap, span })))
+}
 
-    let shared_counter2: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+fn log(env: &Env, n: i32) {
+    env.output.lock().unwrap().write_int(n as i64);
+}
 
-    let log_func = Function::new_native_with_env(&store, Env { output: shared_counter2.clone() }, log);
+fn log_i64(env: &Env, n: i64) {
+    env.output.lock().unwrap().write_int(n);
+}
 
-    fn log(env: &Env, n: i32) {
-        let mut output_ref = env.output.lock().unwrap();
-        output_ref.push(n.to_string());
-    }
+fn log_str(env: &Env, ptr: i32, len: i32) {
+    let s = read_string(env.memory_ref().unwrap(), ptr, len);
+    env.output.lock().unwrap().write_string(&s);
+}
 
-    let import_object = imports! {
-        "env" => {
-            "log" => log_func
-        }
-    };
+fn log_f64(env: &Env, n: f64) {
+    env.output.lock().unwrap().write_float(n);
+}
 
-    let instance = Instance::new(&module, &import_object)?;
+fn i64_to_str(env: &Env, n: i64) -> Result<i32, RuntimeError> {
+    write_string(env.memory_ref().unwrap(), env.alloc_ref().unwrap(), &n.to_string())
+}
 
-    let main = instance.exports.get_function("main")?;
-    main.call(&[])?;
+fn f32_to_str(env: &Env, n: f32) -> Result<i32, RuntimeError> {
+    write_string(env.memory_ref().unwrap(), env.alloc_ref().unwrap(), &n.to_string())
+}
 
-    let foobar = &shared_counter2.lock().unwrap();
-    Ok((*foobar).to_vec())
-}
\ No newline at end of file
+fn f64_to_str(env: &Env, n: f64) -> Result<i32, RuntimeError> {
+    write_string(env.memory_ref().unwrap(), env.alloc_ref().unwrap(), &n.to_string())
+}
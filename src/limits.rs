@@ -0,0 +1,259 @@
+use std::fmt;
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use wasmer::wasmparser::{BinaryReaderError, ImportSectionEntryType, Operator, Parser, Payload, Type as WpType, TypeOrFuncType};
+use wasmer::{
+    ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::ModuleInfo;
+
+/// What a program can use each time the host runs it or calls one of its
+/// functions, `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Roughly one unit per instruction executed.
+    pub fuel: Option<u64>,
+    /// Pages of 64 KiB of linear memory, including the one programs start
+    /// with.
+    pub memory_pages: Option<u32>,
+    /// Functions that can be running at once, the runtime's own helpers
+    /// included.
+    pub call_depth: Option<u32>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Limits::default()
+    }
+}
+
+/// The limit a program ran into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Fuel,
+    Memory,
+    CallDepth,
+}
+
+impl Limit {
+    fn code(self) -> i32 {
+        match self {
+            Limit::Fuel => 1,
+            Limit::Memory => 2,
+            Limit::CallDepth => 3,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<Limit> {
+        match code {
+            1 => Some(Limit::Fuel),
+            2 => Some(Limit::Memory),
+            3 => Some(Limit::CallDepth),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Fuel => write!(f, "the program ran out of fuel"),
+            Limit::Memory => write!(f, "the program ran out of memory"),
+            Limit::CallDepth => write!(f, "the program exceeded the maximum call depth"),
+        }
+    }
+}
+
+// The globals the instrumentation adds to every module. The host sets the
+// first three before it enters the module, and reads `EXHAUSTED` when the
+// module traps.
+pub const MAX_DEPTH: &str = "__max_depth";
+pub const MAX_PAGES: &str = "__max_pages";
+pub const DEPTH: &str = "__depth";
+pub const EXHAUSTED: &str = "__exhausted";
+
+/// The indices of the added globals, which follow the module's own.
+#[derive(Debug, Clone, Copy)]
+struct Globals {
+    max_depth: u32,
+    max_pages: u32,
+    depth: u32,
+    exhausted: u32,
+    /// Holds an operand the instrumentation needs twice.
+    scratch: u32,
+}
+
+impl Globals {
+    fn after(globals: u32) -> Self {
+        Globals {
+            max_depth: globals,
+            max_pages: globals + 1,
+            depth: globals + 2,
+            exhausted: globals + 3,
+            scratch: globals + 4,
+        }
+    }
+}
+
+/// The fuel an instruction takes, for wasmer's `Metering`.
+pub fn fuel_cost(_: &Operator) -> u64 {
+    1
+}
+
+/// Compiler middleware that makes a module count its call depth and check
+/// memory growth against the host's limits. Modules stop with `unreachable`
+/// after recording the limit they ran into in `EXHAUSTED`.
+#[derive(Debug)]
+pub struct Instrumentation {
+    globals: Globals,
+}
+
+impl Instrumentation {
+    /// Instruments the module `wasm`, which needs a compiler of its own as
+    /// the added globals come after the ones it has.
+    pub fn for_module(wasm: &[u8]) -> Result<Self, BinaryReaderError> {
+        let mut globals = 0;
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::ImportSection(imports) => {
+                    for import in imports {
+                        if let ImportSectionEntryType::Global(_) = import?.ty {
+                            globals += 1;
+                        }
+                    }
+                }
+                Payload::GlobalSection(section) => globals += section.get_count(),
+                _ => {}
+            }
+        }
+        Ok(Instrumentation { globals: Globals::after(globals) })
+    }
+}
+
+impl MemoryUsage for Instrumentation {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+impl ModuleMiddleware for Instrumentation {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionInstrumentation { globals: self.globals, blocks: 0, entered: false })
+    }
+
+    fn transform_module_info(&self, info: &mut ModuleInfo) {
+        assert_eq!(info.globals.len() as u32, self.globals.max_depth, "instrumenting a different module");
+
+        let mut add = |name: Option<&str>, ty: Type, init: GlobalInit| {
+            let index = info.globals.push(GlobalType::new(ty, Mutability::Var));
+            info.global_initializers.push(init);
+            if let Some(name) = name {
+                info.exports.insert(name.to_string(), ExportIndex::Global(index));
+            }
+        };
+
+        // In the order of `Globals::after`.
+        add(Some(MAX_DEPTH), Type::I32, GlobalInit::I32Const(-1));
+        add(Some(MAX_PAGES), Type::I32, GlobalInit::I32Const(-1));
+        add(Some(DEPTH), Type::I32, GlobalInit::I32Const(0));
+        add(Some(EXHAUSTED), Type::I32, GlobalInit::I32Const(0));
+        add(None, Type::I32, GlobalInit::I32Const(0));
+    }
+}
+
+#[derive(Debug)]
+struct FunctionInstrumentation {
+    globals: Globals,
+    /// How many blocks the next `end` is nested in, the function's own
+    /// `end` is at zero.
+    blocks: u32,
+    entered: bool,
+}
+
+impl FunctionMiddleware for FunctionInstrumentation {
+    fn feed<'a>(&mut self, operator: Operator<'a>, state: &mut MiddlewareReaderState<'a>) -> Result<(), MiddlewareError> {
+        if !self.entered {
+            self.entered = true;
+            state.extend(self.enter());
+        }
+
+        match operator {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => self.blocks += 1,
+            Operator::End if self.blocks > 0 => self.blocks -= 1,
+            Operator::End | Operator::Return => state.extend(self.leave()),
+            Operator::MemoryGrow { .. } => {
+                state.extend(self.grow(operator));
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        state.push_operator(operator);
+        Ok(())
+    }
+}
+
+impl FunctionInstrumentation {
+    /// Counts the call and stops if it's one too deep.
+    fn enter<'a>(&self) -> Vec<Operator<'a>> {
+        let depth = self.globals.depth;
+        let mut ops = vec![
+            Operator::GlobalGet { global_index: depth },
+            Operator::I32Const { value: 1 },
+            Operator::I32Add,
+            Operator::GlobalSet { global_index: depth },
+            Operator::GlobalGet { global_index: depth },
+            Operator::GlobalGet { global_index: self.globals.max_depth },
+            Operator::I32GtU,
+        ];
+        ops.extend(self.stop_if(Limit::CallDepth));
+        ops
+    }
+
+    fn leave<'a>(&self) -> Vec<Operator<'a>> {
+        let depth = self.globals.depth;
+        vec![
+            Operator::GlobalGet { global_index: depth },
+            Operator::I32Const { value: 1 },
+            Operator::I32Sub,
+            Operator::GlobalSet { global_index: depth },
+        ]
+    }
+
+    /// Grows the memory by the pages on the stack, unless that takes it past
+    /// the limit or the runtime can't.
+    fn grow<'a>(&self, grow: Operator<'a>) -> Vec<Operator<'a>> {
+        let scratch = self.globals.scratch;
+        let mut ops = vec![
+            Operator::GlobalSet { global_index: scratch },
+            Operator::MemorySize { mem: 0, mem_byte: 0 },
+            Operator::GlobalGet { global_index: scratch },
+            Operator::I32Add,
+            Operator::GlobalGet { global_index: self.globals.max_pages },
+            Operator::I32GtU,
+        ];
+        ops.extend(self.stop_if(Limit::Memory));
+        ops.extend([
+            Operator::GlobalGet { global_index: scratch },
+            grow,
+            Operator::GlobalSet { global_index: scratch },
+            Operator::GlobalGet { global_index: scratch },
+            Operator::I32Const { value: -1 },
+            Operator::I32Eq,
+        ]);
+        ops.extend(self.stop_if(Limit::Memory));
+        ops.push(Operator::GlobalGet { global_index: scratch });
+        ops
+    }
+
+    /// Records `limit` and traps if the condition on the stack is true.
+    fn stop_if<'a>(&self, limit: Limit) -> Vec<Operator<'a>> {
+        vec![
+            Operator::If { ty: TypeOrFuncType::Type(WpType::EmptyBlockType) },
+            Operator::I32Const { value: limit.code() },
+            Operator::GlobalSet { global_index: self.globals.exhausted },
+            Operator::Unreachable,
+            Operator::End,
+        ]
+    }
+}
@@ -1,12 +1,280 @@
-mod syntax;
-mod codegen;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::{env, fs, io};
+use teal::{Emit, Session, Style, TealError};
 
-use anyhow::Result;
-use teal::run;
+const USAGE: &str = "\
+Usage: teal <command> [options] <file>
+       teal repl
 
-fn main() -> Result<()> {
-    let code = r#"
-    print true != false;
-    "#;
-    run(code)
+Commands:
+  run <file>               Compile and run a program
+  build <file> [-o <out>]  Compile a program to <out>, the text format if it
+                           ends in .wat, <file>.wasm by default
+  check <file>             Report the errors in a program without running it
+  repl                     Run the lines typed in one at a time, printing the
+                           value of expressions
+
+Options:
+  --emit tokens|ast|wat    Print a stage of the compiler before the command
+  -h, --help               Print this message
+";
+
+// Exit codes, as in sysexits.h.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run,
+    Build,
+    Check,
+    Repl,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Command,
+    /// The program to compile, `None` for the REPL.
+    path: Option<PathBuf>,
+    output: Option<PathBuf>,
+    emit: Option<Emit>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprint!("{}", USAGE);
+        exit(EX_USAGE);
+    }
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        exit(0);
+    }
+
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprint!("error: {}\n\n{}", message, USAGE);
+            exit(EX_USAGE);
+        }
+    };
+
+    exit(run(&options));
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut args = args.into_iter();
+
+    let command = match args.next().as_deref() {
+        Some("run") => Command::Run,
+        Some("build") => Command::Build,
+        Some("check") => Command::Check,
+        Some("repl") => Command::Repl,
+        Some(command) => return Err(format!("unknown command '{}'", command)),
+        None => return Err("missing command".to_string()),
+    };
+
+    let mut path = None;
+    let mut output = None;
+    let mut emit = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                let out = args.next().ok_or("'-o' needs a file name")?;
+                output = Some(PathBuf::from(out));
+            }
+            "--emit" => {
+                emit = Some(match args.next().as_deref() {
+                    Some("tokens") => Emit::Tokens,
+                    Some("ast") => Emit::Ast,
+                    Some("wat") => Emit::Wat,
+                    Some(stage) => return Err(format!("can't emit '{}', expected tokens, ast or wat", stage)),
+                    None => return Err("'--emit' needs a stage".to_string()),
+                });
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if path.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    if output.is_some() && command != Command::Build {
+        return Err("'-o' is only used by build".to_string());
+    }
+
+    if command == Command::Repl {
+        if let Some(path) = path {
+            return Err(format!("unexpected argument '{}'", path.display()));
+        }
+        if emit.is_some() {
+            return Err("'--emit' isn't used by repl".to_string());
+        }
+    } else if path.is_none() {
+        return Err("missing file".to_string());
+    }
+
+    Ok(Options { command, path, output, emit })
+}
+
+/// Runs the command and returns the exit code.
+fn run(options: &Options) -> i32 {
+    let path = match &options.path {
+        Some(path) => path,
+        None => return repl(),
+    };
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("error: can't read {}: {}", path.display(), e);
+            return EX_IOERR;
+        }
+    };
+
+    if let Some(stage) = options.emit {
+        match teal::emit(&source, stage) {
+            Ok(output) => print!("{}", output),
+            Err(e) => return report(&source, e),
+        }
+    }
+
+    let result = match options.command {
+        Command::Run => teal::run(&source),
+        Command::Build => build(&source, path, options.output.as_deref()),
+        Command::Check => teal::check(&source).map_err(Into::into),
+        Command::Repl => unreachable!("the REPL has no file"),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => report(&source, e),
+    }
+}
+
+fn build(source: &str, path: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    let output = output.map_or_else(|| path.with_extension("wasm"), Path::to_path_buf);
+
+    let bytes = if is_wat(&output) {
+        teal::compile_to_wat(source)?.into_bytes()
+    } else {
+        teal::compile(source)?
+    };
+
+    fs::write(&output, bytes).map_err(|e| {
+        anyhow::Error::new(e).context(format!("can't write {}", output.display()))
+    })
+}
+
+/// Reads inputs from stdin until it ends and evaluates them in one session.
+/// Errors are reported and the session goes on.
+fn repl() -> i32 {
+    let mut session = Session::new();
+    let mut lines = io::stdin().lock().lines();
+
+    loop {
+        // Read lines until they make a complete input.
+        let mut input = String::new();
+        loop {
+            print!("{}", if input.is_empty() { "> " } else { "... " });
+            let _ = io::stdout().flush();
+
+            match lines.next() {
+                Some(Ok(line)) => input.push_str(&line),
+                Some(Err(e)) => {
+                    eprintln!("error: can't read stdin: {}", e);
+                    return EX_IOERR;
+                }
+                None => {
+                    println!();
+                    return 0;
+                }
+            }
+            input.push('\n');
+
+            if input.trim().is_empty() || Session::is_complete(&input) {
+                break;
+            }
+        }
+
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        match session.eval(&input) {
+            Ok(output) => {
+                for line in output {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => {
+                report(&input, e.into());
+            }
+        }
+    }
+}
+
+fn is_wat(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "wat")
+}
+
+/// Prints `error` to stderr and returns the exit code for it.
+fn report(source: &str, error: anyhow::Error) -> i32 {
+    let error = match error.downcast::<TealError>() {
+        Ok(error) => error,
+        Err(error) => {
+            eprintln!("error: {:#}", error);
+            let io = error.chain().any(|cause| cause.is::<io::Error>());
+            return if io { EX_IOERR } else { EX_SOFTWARE };
+        }
+    };
+
+    let style = if io::stderr().is_terminal() { Style::Ansi } else { Style::Plain };
+    for diagnostic in error.diagnostics() {
+        eprint!("{}", diagnostic.render(source, style));
+    }
+
+    if error.is_compile_error() { EX_DATAERR } else { EX_SOFTWARE }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parse_build() {
+        let options = parse_args(args("build main.tl -o out.wat --emit ast")).unwrap();
+        assert_eq!(options, Options {
+            command: Command::Build,
+            path: Some(PathBuf::from("main.tl")),
+            output: Some(PathBuf::from("out.wat")),
+            emit: Some(Emit::Ast),
+        });
+    }
+
+    #[test]
+    fn parse_repl() {
+        let options = parse_args(args("repl")).unwrap();
+        assert_eq!(options, Options { command: Command::Repl, path: None, output: None, emit: None });
+    }
+
+    #[test]
+    fn reject_bad_arguments() {
+        assert_eq!(parse_args(args("compile main.tl")), Err("unknown command 'compile'".to_string()));
+        assert_eq!(parse_args(args("run")), Err("missing file".to_string()));
+        assert_eq!(parse_args(args("repl a.tl")), Err("unexpected argument 'a.tl'".to_string()));
+        assert_eq!(parse_args(args("run a.tl b.tl")), Err("unexpected argument 'b.tl'".to_string()));
+        assert_eq!(parse_args(args("run a.tl -o a.wasm")), Err("'-o' is only used by build".to_string()));
+        assert_eq!(
+            parse_args(args("check a.tl --emit bytes")),
+            Err("can't emit 'bytes', expected tokens, ast or wat".to_string())
+        );
+    }
 }
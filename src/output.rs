@@ -0,0 +1,91 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Where a running program's `print`s go. Each call is passed one printed
+/// value: integers and bools as `write_int`, `f64`s as `write_float` and
+/// everything else as text.
+pub trait Output: Send {
+    fn write_int(&mut self, n: i64);
+
+    fn write_float(&mut self, n: f64);
+
+    fn write_string(&mut self, s: &str);
+
+    /// Called once the program stops, whether it finished or not.
+    fn flush(&mut self) {}
+}
+
+/// Prints a line per value to stdout. Lines that can't be written, say
+/// because the reader went away, are dropped rather than stopping the host.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn write_int(&mut self, n: i64) {
+        let _ = writeln!(io::stdout().lock(), "{}", n);
+    }
+
+    fn write_float(&mut self, n: f64) {
+        let _ = writeln!(io::stdout().lock(), "{}", n);
+    }
+
+    fn write_string(&mut self, s: &str) {
+        let _ = writeln!(io::stdout().lock(), "{}", s);
+    }
+
+    fn flush(&mut self) {
+        // The program has already run, a failed flush is not its error.
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Collects the printed values as text. Clones share the same buffer, so a
+/// clone can be kept to read what a program given the buffer printed.
+#[derive(Debug, Clone, Default)]
+pub struct Buffer(Arc<Mutex<Vec<String>>>);
+
+impl Buffer {
+    pub fn new() -> Self {
+        Buffer::default()
+    }
+
+    /// Takes what was printed so far, one entry per value.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    fn push(&self, line: String) {
+        self.0.lock().unwrap().push(line);
+    }
+}
+
+impl Output for Buffer {
+    fn write_int(&mut self, n: i64) {
+        self.push(n.to_string());
+    }
+
+    fn write_float(&mut self, n: f64) {
+        self.push(n.to_string());
+    }
+
+    fn write_string(&mut self, s: &str) {
+        self.push(s.to_string());
+    }
+}
+
+/// Calls a function with each printed value, as text.
+pub struct Callback<F>(pub F);
+
+impl<F: FnMut(&str) + Send> Output for Callback<F> {
+    fn write_int(&mut self, n: i64) {
+        (self.0)(&n.to_string());
+    }
+
+    fn write_float(&mut self, n: f64) {
+        (self.0)(&n.to_string());
+    }
+
+    fn write_string(&mut self, s: &str) {
+        (self.0)(s);
+    }
+}
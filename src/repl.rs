@@ -0,0 +1,196 @@
+use crate::engine::Engine;
+use crate::error::TealError;
+use crate::interp::value::Value;
+use crate::resolve::resolve_shadowing_globals;
+use crate::syntax::{lex, Span, SyntaxError, TokenType};
+use crate::syntax::ast::{Expr, ExprKind, Identifier, LiteralExpr, Program, Type};
+use crate::{parse_source, types};
+
+/// The state an interactive session keeps between inputs.
+///
+/// Every input is compiled to a module of its own, after the declarations of
+/// the inputs before it: globals as `let`s initialized with their last value
+/// and functions as they were written. A global declared again shadows the
+/// earlier one, functions declared before keep seeing the one they were
+/// declared after. An input that fails leaves the session as it was.
+#[derive(Default)]
+pub struct Session {
+    engine: Engine,
+    /// The globals and functions declared so far, in order.
+    declarations: Vec<Expr>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// Compiles and runs `input` and returns what it printed. The value of
+    /// each expression at the top level of the input is printed too.
+    pub fn eval(&mut self, input: &str) -> Result<Vec<String>, TealError> {
+        let functions: Vec<Identifier> = self.declarations.iter().filter_map(fun_name).cloned().collect();
+        let snippet = parse_source(input, &functions)?;
+
+        // A function declared again replaces the earlier one.
+        let redefined: Vec<&Identifier> = snippet.iter().filter_map(fun_name).collect();
+        let mut program: Program = self.declarations.iter()
+            .filter(|expr| !fun_name(expr).is_some_and(|ident| redefined.contains(&ident)))
+            .cloned()
+            .collect();
+        let inputs = program.len()..program.len() + snippet.len();
+        program.extend(snippet);
+        let declarations = program.clone();
+
+        let program = resolve_shadowing_globals(program)?;
+        let mut program = types::check(program)?;
+
+        for expr in &mut program[inputs] {
+            if expr.is_value() && expr.ty != Type::Unit {
+                let span = expr.span;
+                let value = std::mem::replace(expr, Expr::block(vec![], span));
+                *expr = Expr::print(value, span);
+                expr.ty = Type::Unit;
+            }
+        }
+
+        // What each declaration was checked as, globals by their resolved
+        // name.
+        let checked: Vec<Option<Expr>> = program.iter()
+            .map(|expr| match &expr.kind {
+                ExprKind::LetAssign { .. } | ExprKind::Fun { .. } => Some(expr.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let instance = self.engine.compile_program(program)?.instantiate()?;
+        instance.run()?;
+
+        let mut kept = vec![];
+        for (mut declaration, checked) in declarations.into_iter().zip(checked) {
+            match (&mut declaration.kind, checked.map(|expr| expr.kind)) {
+                // A global of no value can't be used anyway.
+                (ExprKind::LetAssign { ident, .. }, Some(ExprKind::LetAssign { ident: unique, ty, .. }))
+                    if ty != Type::Unit =>
+                {
+                    let value = instance.global(&unique, ty)?;
+                    kept.push(declare_global(ident, &value));
+                }
+                (ExprKind::Fun { params, ret, .. }, Some(ExprKind::Fun { params: checked, ret: checked_ret, .. })) => {
+                    // Later inputs can't change the types a function was
+                    // checked with.
+                    for (param, checked) in params.iter_mut().zip(checked) {
+                        param.ty = checked.ty;
+                    }
+                    *ret = checked_ret;
+                    kept.push(declaration);
+                }
+                _ => {}
+            }
+        }
+        self.declarations = kept;
+
+        Ok(instance.take_output())
+    }
+
+    /// Whether `input` can be evaluated or more lines are needed to finish
+    /// it: a string or bracket is still open, a block was started, or an
+    /// indented block hasn't been ended by an empty line.
+    pub fn is_complete(input: &str) -> bool {
+        let (tokens, errors) = lex(input);
+        if errors.iter().any(|e| matches!(e, SyntaxError::UnterminatedString(_))) {
+            return false;
+        }
+
+        let mut depth = 0;
+        for token in &tokens {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth > 0 {
+            return false;
+        }
+
+        let last = tokens.iter()
+            .rev()
+            .find(|t| !matches!(t.token_type, TokenType::Newline | TokenType::Dedent | TokenType::EOF));
+        if last.is_some_and(|t| t.token_type == TokenType::Colon) {
+            return false;
+        }
+
+        let layout = tokens.iter().any(|t| t.token_type == TokenType::Indent);
+        !layout || input.ends_with("\n\n")
+    }
+}
+
+fn fun_name(expr: &Expr) -> Option<&Identifier> {
+    match &expr.kind {
+        ExprKind::Fun { ident, .. } => Some(ident),
+        _ => None,
+    }
+}
+
+/// `let ident: type = value;`
+fn declare_global(ident: &Identifier, value: &Value) -> Expr {
+    let literal = match value {
+        Value::I32(n) => LiteralExpr::Number(*n as i64),
+        Value::I64(n) => LiteralExpr::Number(*n),
+        Value::F32(n) => LiteralExpr::Float(*n as f64),
+        Value::F64(n) => LiteralExpr::Float(*n),
+        Value::Bool(true) => LiteralExpr::True,
+        Value::Bool(false) => LiteralExpr::False,
+        Value::Str(s) => LiteralExpr::String(s.to_string()),
+        Value::Unit => unreachable!("unit globals aren't kept"),
+    };
+
+    let span = Span::default();
+    Expr::let_assign(ident.clone(), value.ty(), Expr::new(ExprKind::Literal(literal), span), span)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repl::Session;
+
+    #[test]
+    fn keep_globals_and_functions() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("let x = 20;").unwrap(), Vec::<String>::new());
+        assert_eq!(session.eval("fun twice(n: i32) -> i32 { n * 2 }").unwrap(), Vec::<String>::new());
+        assert_eq!(session.eval("x = twice(x) + 2;").unwrap(), Vec::<String>::new());
+        assert_eq!(session.eval("x").unwrap(), vec!["42"]);
+        assert_eq!(session.eval("let s = \"x is $x\";\nprint s;").unwrap(), vec!["x is 42"]);
+        assert_eq!(session.eval("s").unwrap(), vec!["x is 42"]);
+    }
+
+    #[test]
+    fn redeclare_globals_and_functions() {
+        let mut session = Session::new();
+        session.eval("let x = 1;\nfun f() -> i32 { x }").unwrap();
+        session.eval("let x = \"one\";").unwrap();
+        assert_eq!(session.eval("x\nf()").unwrap(), vec!["one", "1"]);
+        assert_eq!(session.eval("fun f() -> string { x }\nf()").unwrap(), vec!["one"]);
+        session.eval("double n:\n    n * 2\n\n").unwrap();
+        assert_eq!(session.eval("double 21").unwrap(), vec!["42"]);
+    }
+
+    #[test]
+    fn failed_input_leaves_session_unchanged() {
+        let mut session = Session::new();
+        session.eval("let x = 1;").unwrap();
+        assert!(session.eval("x = 2;\nprint 1 / 0;").is_err());
+        assert!(session.eval("print y;").is_err());
+        assert_eq!(session.eval("x").unwrap(), vec!["1"]);
+    }
+
+    #[test]
+    fn wait_for_unfinished_input() {
+        assert!(Session::is_complete("let x = 1;"));
+        assert!(!Session::is_complete("fun f() {"));
+        assert!(!Session::is_complete("print \"abc"));
+        assert!(!Session::is_complete("double x:"));
+        assert!(!Session::is_complete("double x:\n    x * 2\n"));
+        assert!(Session::is_complete("double x:\n    x * 2\n\n"));
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use crate::syntax::ast::Identifier;
+use crate::syntax::Span;
+
+#[derive(Debug, PartialEq)]
+pub enum ResolveError {
+    UndefinedVariable(Identifier, Span),
+    /// A variable declared twice in the same scope, with the span of the
+    /// first declaration.
+    DuplicateVariable(Identifier, Span, Span),
+    /// A function declared twice, with the span of the first declaration.
+    DuplicateFunction(Identifier, Span, Span),
+    /// A parameter listed twice, with the span of its function.
+    DuplicateParameter(Identifier, Span),
+    /// A function with the name of one the runtime provides.
+    ReservedFunction(Identifier, Span),
+    /// A variable with the name of a function, which a bare use of the name
+    /// would call, with the span of the function.
+    FunctionVariable(Identifier, Span, Span),
+}
+
+impl ResolveError {
+    pub fn span(&self) -> Span {
+        match self {
+            ResolveError::UndefinedVariable(_, span)
+            | ResolveError::DuplicateVariable(_, span, _)
+            | ResolveError::DuplicateFunction(_, span, _)
+            | ResolveError::DuplicateParameter(_, span)
+            | ResolveError::ReservedFunction(_, span)
+            | ResolveError::FunctionVariable(_, span, _) => *span,
+        }
+    }
+
+    /// Where the name was declared first, for duplicates.
+    pub fn previous(&self) -> Option<Span> {
+        match self {
+            ResolveError::DuplicateVariable(_, _, previous)
+            | ResolveError::DuplicateFunction(_, _, previous)
+            | ResolveError::FunctionVariable(_, _, previous) => Some(*previous),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ResolveError::UndefinedVariable(ident, _) => format!("undefined variable '{}'", ident),
+            ResolveError::DuplicateVariable(ident, _, _) => {
+                format!("'{}' is already declared in this scope", ident)
+            }
+            ResolveError::DuplicateFunction(ident, _, _) => format!("function '{}' is already declared", ident),
+            ResolveError::DuplicateParameter(ident, _) => format!("duplicate parameter '{}'", ident),
+            ResolveError::ReservedFunction(ident, _) => format!("'{}' is the name of a runtime function", ident),
+            ResolveError::FunctionVariable(ident, _, _) => format!("'{}' is already declared as a function", ident),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.span().line, self.message())
+    }
+}
+
+impl std::error::Error for ResolveError {}
@@ -0,0 +1,114 @@
+use crate::resolve::error::ResolveError;
+use crate::resolve::resolver::Resolver;
+use crate::syntax::ast::Program;
+
+mod resolver;
+pub mod error;
+
+/// Binds every variable in `program` to its declaration. Variables are
+/// renamed so each declaration has a name of its own, shadowed variables
+/// don't clash in the generated code.
+pub fn resolve(program: Program) -> Result<Program, Vec<ResolveError>> {
+    resolve_with(Resolver::new(), program)
+}
+
+/// Like `resolve`, but a global declared again shadows the earlier one
+/// instead of being an error.
+pub fn resolve_shadowing_globals(program: Program) -> Result<Program, Vec<ResolveError>> {
+    resolve_with(Resolver::shadowing_globals(), program)
+}
+
+fn resolve_with(mut resolver: Resolver, mut program: Program) -> Result<Program, Vec<ResolveError>> {
+    resolver.resolve_program(&mut program);
+
+    let errors = resolver.into_errors();
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resolve::error::ResolveError;
+    use crate::resolve::resolve;
+    use crate::syntax::{lex, parse, Span};
+    use crate::syntax::ast::{ExprKind, Program};
+
+    fn resolve_source(source: &str) -> Result<Program, Vec<ResolveError>> {
+        let (mut tokens, lex_errors) = lex(source);
+        assert!(lex_errors.is_empty());
+        let (program, errors) = parse(&mut tokens, &[]);
+        assert!(errors.is_empty());
+        resolve(program)
+    }
+
+    /// The names of the variables `print` statements refer to, in order.
+    fn printed_names(exprs: &[crate::syntax::ast::Expr], names: &mut Vec<String>) {
+        for expr in exprs {
+            match &expr.kind {
+                ExprKind::Print { value } => {
+                    if let ExprKind::LetGet { ident } = &value.kind {
+                        names.push(ident.clone());
+                    }
+                }
+                ExprKind::Block(exprs) => printed_names(exprs, names),
+                ExprKind::Fun { body, .. } => printed_names(body, names),
+                ExprKind::IfElse { then, .. } => printed_names(std::slice::from_ref(then.as_ref()), names),
+                ExprKind::For { body, .. } => printed_names(std::slice::from_ref(body.as_ref()), names),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn rename_shadowed_variables() {
+        let source = "let x = 1;\n{\n    let x = 2;\n    print x;\n}\nprint x;\nfun f(x: i32) { print x; }\n";
+        let program = resolve_source(source).unwrap();
+
+        let mut names = vec![];
+        printed_names(&program, &mut names);
+        assert_eq!(names, vec!["x#1", "x", "x#2"]);
+    }
+
+    #[test]
+    fn functions_only_see_globals() {
+        let errors = resolve_source("{\n    let a = 1;\n    fun f() { print a; }\n}\n").unwrap_err();
+        assert_eq!(errors, vec![ResolveError::UndefinedVariable("a".to_string(), Span::new(37, 38, 3, 21))]);
+    }
+
+    #[test]
+    fn report_every_undefined_variable() {
+        let errors = resolve_source("let total = 1;\nprint totl;\nfor i in 0..3 { print i; }\nprint i;\n").unwrap_err();
+        assert_eq!(errors, vec![
+            ResolveError::UndefinedVariable("totl".to_string(), Span::new(21, 25, 2, 7)),
+            ResolveError::UndefinedVariable("i".to_string(), Span::new(60, 61, 4, 7)),
+        ]);
+    }
+
+    #[test]
+    fn reject_duplicates() {
+        let errors = resolve_source("let a = 1;\nlet a = 2;\nfun f(b, b) { }\nfun f() { }\n").unwrap_err();
+        assert_eq!(errors, vec![
+            ResolveError::DuplicateVariable("a".to_string(), Span::new(11, 21, 2, 1), Span::new(0, 10, 1, 1)),
+            ResolveError::DuplicateParameter("b".to_string(), Span::new(22, 37, 3, 1)),
+            ResolveError::DuplicateFunction("f".to_string(), Span::new(38, 49, 4, 1), Span::new(22, 37, 3, 1)),
+        ]);
+    }
+
+    #[test]
+    fn reject_functions_named_like_the_runtime() {
+        let errors = resolve_source("fun log(n: i32) { print n + 100; }\nlog(3);\n").unwrap_err();
+        assert_eq!(errors, vec![ResolveError::ReservedFunction("log".to_string(), Span::new(0, 34, 1, 1))]);
+    }
+
+    #[test]
+    fn reject_variables_named_like_functions() {
+        let errors = resolve_source("let f = 1;\nfun g(h: i32) { }\nfun f() { }\nfun h() { }\n").unwrap_err();
+        assert_eq!(errors, vec![
+            ResolveError::FunctionVariable("f".to_string(), Span::new(0, 10, 1, 1), Span::new(29, 40, 3, 1)),
+            ResolveError::FunctionVariable("h".to_string(), Span::new(11, 28, 2, 1), Span::new(41, 52, 4, 1)),
+        ]);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use crate::codegen::is_runtime_function;
+use crate::resolve::error::ResolveError;
+use crate::syntax::ast::*;
+use crate::syntax::Span;
+
+/// A variable in scope.
+#[derive(Clone)]
+struct Variable {
+    /// The name the variable is known by after resolution.
+    unique: Identifier,
+    /// Where it was declared.
+    span: Span,
+}
+
+type Scope = HashMap<Identifier, Variable>;
+
+pub struct Resolver {
+    /// The scopes enclosing the expression being resolved, the first one
+    /// holds the globals.
+    scopes: Vec<Scope>,
+    /// How many variables of each name have been declared so far.
+    declarations: HashMap<Identifier, usize>,
+    /// Where each function was declared.
+    functions: HashMap<Identifier, Span>,
+    /// Every variable declared, a function declared later can't take its
+    /// name either.
+    variables: Vec<(Identifier, Span)>,
+    errors: Vec<ResolveError>,
+    /// Whether a global can be declared again, the later declaration
+    /// shadows the earlier one like in a nested scope.
+    shadow_globals: bool,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: vec![HashMap::new()],
+            declarations: HashMap::new(),
+            functions: HashMap::new(),
+            variables: vec![],
+            errors: vec![],
+            shadow_globals: false,
+        }
+    }
+
+    /// A resolver that lets globals be redeclared, for inputs that build on
+    /// earlier ones.
+    pub fn shadowing_globals() -> Self {
+        Resolver { shadow_globals: true, ..Resolver::new() }
+    }
+
+    pub fn resolve_program(&mut self, program: &mut Program) {
+        for expr in program.iter_mut() {
+            self.resolve_expr(expr);
+        }
+
+        // A bare use of the name would call the function.
+        for (ident, span) in std::mem::take(&mut self.variables) {
+            if let Some(&function) = self.functions.get(&ident) {
+                self.errors.push(ResolveError::FunctionVariable(ident, span, function));
+            }
+        }
+        self.errors.sort_by_key(|error| error.span().start);
+    }
+
+    pub fn into_errors(self) -> Vec<ResolveError> {
+        self.errors
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        let span = expr.span;
+
+        match &mut expr.kind {
+            ExprKind::Block(exprs) => {
+                self.scopes.push(HashMap::new());
+                for e in exprs {
+                    self.resolve_expr(e);
+                }
+                self.scopes.pop();
+            }
+            ExprKind::Binary { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            ExprKind::Unary { expr, .. } | ExprKind::Print { value: expr } => self.resolve_expr(expr),
+            ExprKind::LetAssign { ident, initializer, .. } => {
+                // The initializer still sees a variable this one shadows.
+                self.resolve_expr(initializer);
+                *ident = self.declare(ident, span);
+            }
+            ExprKind::LetGet { ident } => self.lookup(ident, span),
+            ExprKind::LetSet { ident, expr } => {
+                self.resolve_expr(expr);
+                self.lookup(ident, span);
+            }
+            ExprKind::IfElse { condition, then, else_ } => {
+                self.resolve_expr(condition);
+                self.resolve_branch(then);
+                if let Some(else_) = else_ {
+                    self.resolve_branch(else_);
+                }
+            }
+            ExprKind::Fun { ident, params, body, .. } => self.resolve_fun(ident, params, body, span),
+            ExprKind::Extern { ident, params, .. } => {
+                self.declare_function(ident, span);
+                self.check_parameters(params, span);
+            }
+            ExprKind::Call { callee, args } => {
+                // Functions live in a namespace of their own, the checker
+                // looks them up.
+                if !matches!(callee.kind, ExprKind::LetGet { .. }) {
+                    self.resolve_expr(callee);
+                }
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            ExprKind::Return { value } => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.resolve_expr(condition);
+                self.resolve_branch(body);
+            }
+            ExprKind::For { ident, start, end, body } => {
+                self.resolve_expr(start);
+                self.resolve_expr(end);
+
+                // The counter is scoped to the loop.
+                self.scopes.push(HashMap::new());
+                *ident = self.declare(ident, span);
+                self.resolve_expr(body);
+                self.scopes.pop();
+            }
+            ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    self.resolve_expr(part);
+                }
+            }
+            ExprKind::Break | ExprKind::Continue | ExprKind::Literal(_) => {}
+        }
+    }
+
+    fn resolve_branch(&mut self, branch: &mut Expr) {
+        self.scopes.push(HashMap::new());
+        self.resolve_expr(branch);
+        self.scopes.pop();
+    }
+
+    fn resolve_fun(&mut self, ident: &Identifier, params: &mut [Param], body: &mut BlockDecl, span: Span) {
+        self.declare_function(ident, span);
+
+        // Functions see the globals and their own parameters.
+        let globals = self.scopes[0].clone();
+        let enclosing = std::mem::replace(&mut self.scopes, vec![globals, HashMap::new()]);
+
+        for param in params.iter_mut() {
+            if self.scopes[1].contains_key(&param.ident) {
+                self.errors.push(ResolveError::DuplicateParameter(param.ident.clone(), span));
+            } else {
+                param.ident = self.declare(&param.ident, span);
+            }
+        }
+
+        for expr in body {
+            self.resolve_expr(expr);
+        }
+
+        self.scopes = enclosing;
+    }
+
+    fn declare_function(&mut self, ident: &Identifier, span: Span) {
+        if is_runtime_function(ident) {
+            self.errors.push(ResolveError::ReservedFunction(ident.clone(), span));
+        }
+
+        match self.functions.get(ident) {
+            Some(&previous) => {
+                self.errors.push(ResolveError::DuplicateFunction(ident.clone(), span, previous));
+            }
+            None => {
+                self.functions.insert(ident.clone(), span);
+            }
+        }
+    }
+
+    /// Reports the parameters of a function without a body listed twice.
+    fn check_parameters(&mut self, params: &[Param], span: Span) {
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].iter().any(|p| p.ident == param.ident) {
+                self.errors.push(ResolveError::DuplicateParameter(param.ident.clone(), span));
+            }
+        }
+    }
+
+    /// Declares `ident` in the innermost scope and returns its unique name,
+    /// the first variable of a name keeps it.
+    fn declare(&mut self, ident: &Identifier, span: Span) -> Identifier {
+        self.variables.push((ident.clone(), span));
+
+        let count = self.declarations.entry(ident.clone()).or_insert(0);
+        let unique = match *count {
+            0 => ident.clone(),
+            n => format!("{}#{}", ident, n),
+        };
+        *count += 1;
+
+        let shadowing = self.shadow_globals && self.scopes.len() == 1;
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(previous) = scope.get(ident).filter(|_| !shadowing) {
+            self.errors.push(ResolveError::DuplicateVariable(ident.clone(), span, previous.span));
+        }
+        scope.insert(ident.clone(), Variable { unique: unique.clone(), span });
+
+        unique
+    }
+
+    /// Replaces `ident` with the unique name of the variable it refers to.
+    fn lookup(&mut self, ident: &mut Identifier, span: Span) {
+        let variable = self.scopes.iter().rev().find_map(|scope| scope.get(ident.as_str()));
+        match variable {
+            Some(variable) => *ident = variable.unique.clone(),
+            None => self.errors.push(ResolveError::UndefinedVariable(ident.clone(), span)),
+        }
+    }
+}
@@ -1,13 +1,22 @@
+use std::fmt;
 use crate::syntax::error::{ParserError, ParseResult};
-use crate::syntax::token::TokenType;
+use crate::syntax::token::{Span, Token, TokenType};
 
 // TODO: Change to &[Expr].
 pub type Program = Vec<Expr>;
 pub type Identifier = String;
 pub type BlockDecl = Vec<Expr>;
 
-#[derive(PartialEq, Debug)]
-pub enum Expr {
+#[derive(PartialEq, Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// Filled in by the type checker.
+    pub ty: Type,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ExprKind {
     Block(Vec<Expr>),
     Binary {
         left: Box<Expr>,
@@ -20,6 +29,8 @@ pub enum Expr {
     },
     LetAssign {
         ident: Identifier,
+        /// The declared type, inferred from the initializer if not annotated.
+        ty: Type,
         initializer: Box<Expr>,
     },
     LetGet {
@@ -39,94 +50,282 @@ pub enum Expr {
     },
     Fun {
         ident: Identifier,
-        params: Vec<Identifier>,
+        params: Vec<Param>,
+        ret: Type,
         body: BlockDecl,
     },
+    /// `extern fun ident(params) -> ret;`, a function the host provides.
+    Extern {
+        ident: Identifier,
+        params: Vec<Param>,
+        ret: Type,
+    },
     Call {
         callee: Box<Expr>,
         args: Vec<Expr>,
     },
+    Return {
+        value: Option<Box<Expr>>,
+    },
+    While {
+        condition: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `for ident in start..end`, counts up from `start` to `end - 1`.
+    For {
+        ident: Identifier,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Break,
+    Continue,
+    Interpolation(Vec<Expr>),
     Literal(LiteralExpr),
 }
 
 impl Expr {
-    pub fn binary(left: Expr, op: BinaryOperator, right: Expr) -> Self {
-        Expr::Binary {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span, ty: Type::Unknown }
+    }
+
+    /// Whether the expression produces a value, as opposed to a statement
+    /// like `print` or `let` that is only run for its effect.
+    pub fn is_value(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Binary { .. }
+            | ExprKind::Unary { .. }
+            | ExprKind::LetGet { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Interpolation(_)
+            | ExprKind::Literal(_)
+        )
+    }
+
+    /// Whether every path through the expression ends in a `return`.
+    pub fn always_returns(&self) -> bool {
+        match &self.kind {
+            ExprKind::Return { .. } => true,
+            ExprKind::Block(exprs) => exprs.iter().any(Expr::always_returns),
+            ExprKind::IfElse { then, else_: Some(else_), .. } => {
+                then.always_returns() && else_.always_returns()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn block(exprs: Vec<Expr>, span: Span) -> Self {
+        Expr::new(ExprKind::Block(exprs), span)
+    }
+
+    pub fn binary(left: Expr, op: BinaryOperator, right: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::Binary {
             left: Box::new(left),
             op,
             right: Box::new(right),
-        }
+        }, span)
     }
 
-    pub fn unary(op: UnaryOperator, expr: Expr) -> Self {
-        Expr::Unary { op, expr: Box::new(expr) }
+    pub fn unary(op: UnaryOperator, expr: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::Unary { op, expr: Box::new(expr) }, span)
     }
 
-    pub fn let_assign(ident: Identifier, initializer: Expr) -> Self {
-        Expr::LetAssign {
+    pub fn let_assign(ident: Identifier, ty: Type, initializer: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::LetAssign {
             ident,
+            ty,
             initializer: Box::new(initializer),
-        }
+        }, span)
     }
 
-    pub fn let_get(ident: Identifier) -> Self {
-        Expr::LetGet { ident }
+    pub fn let_get(ident: Identifier, span: Span) -> Self {
+        Expr::new(ExprKind::LetGet { ident }, span)
     }
 
-    pub fn let_set(ident: Identifier, expr: Expr) -> Self {
-        Expr::LetSet {
+    pub fn let_set(ident: Identifier, expr: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::LetSet {
             ident,
             expr: Box::new(expr),
-        }
+        }, span)
     }
 
-    pub fn fun(ident: Identifier, params: Vec<Identifier>, body: BlockDecl) -> Self {
-        Expr::Fun { ident, params, body }
+    pub fn fun(ident: Identifier, params: Vec<Param>, ret: Type, body: BlockDecl, span: Span) -> Self {
+        Expr::new(ExprKind::Fun { ident, params, ret, body }, span)
     }
 
-    pub fn call(callee: Expr, args: Vec<Expr>) -> Self {
-        Expr::Call { callee: Box::new(callee), args }
+    pub fn extern_(ident: Identifier, params: Vec<Param>, ret: Type, span: Span) -> Self {
+        Expr::new(ExprKind::Extern { ident, params, ret }, span)
     }
 
-    pub fn number(n: i32) -> Expr {
-        Expr::Literal(LiteralExpr::Number(n))
+    pub fn call(callee: Expr, args: Vec<Expr>, span: Span) -> Self {
+        Expr::new(ExprKind::Call { callee: Box::new(callee), args }, span)
     }
 
-    pub fn string(s: String) -> Expr {
-        Expr::Literal(LiteralExpr::String(s))
+    pub fn return_(value: Option<Expr>, span: Span) -> Self {
+        Expr::new(ExprKind::Return { value: value.map(Box::new) }, span)
     }
 
-    pub fn true_() -> Expr {
-        Expr::Literal(LiteralExpr::True)
+    pub fn while_(condition: Expr, body: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+        }, span)
     }
 
-    pub fn false_() -> Expr {
-        Expr::Literal(LiteralExpr::False)
+    pub fn for_(ident: Identifier, start: Expr, end: Expr, body: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::For {
+            ident,
+            start: Box::new(start),
+            end: Box::new(end),
+            body: Box::new(body),
+        }, span)
     }
 
-    pub fn print(value: Expr) -> Self {
-        Expr::Print { value: Box::new(value) }
+    pub fn break_(span: Span) -> Self {
+        Expr::new(ExprKind::Break, span)
     }
 
-    pub fn if_else(condition: Expr, then: Expr, else_: Option<Expr>) -> Self {
-        let else_boxed = match else_ {
-            None => None,
-            Some(e) => Some(Box::new(e)),
-        };
+    pub fn continue_(span: Span) -> Self {
+        Expr::new(ExprKind::Continue, span)
+    }
+
+    pub fn interpolation(parts: Vec<Expr>, span: Span) -> Self {
+        Expr::new(ExprKind::Interpolation(parts), span)
+    }
+
+    pub fn number(n: i64, span: Span) -> Expr {
+        Expr::new(ExprKind::Literal(LiteralExpr::Number(n)), span)
+    }
 
-        Expr::IfElse { condition: Box::new(condition), then: Box::new(then), else_: else_boxed }
+    pub fn float(n: f64, span: Span) -> Expr {
+        Expr::new(ExprKind::Literal(LiteralExpr::Float(n)), span)
+    }
+
+    pub fn string(s: String, span: Span) -> Expr {
+        Expr::new(ExprKind::Literal(LiteralExpr::String(s)), span)
+    }
+
+    pub fn true_(span: Span) -> Expr {
+        Expr::new(ExprKind::Literal(LiteralExpr::True), span)
+    }
+
+    pub fn false_(span: Span) -> Expr {
+        Expr::new(ExprKind::Literal(LiteralExpr::False), span)
+    }
+
+    pub fn print(value: Expr, span: Span) -> Self {
+        Expr::new(ExprKind::Print { value: Box::new(value) }, span)
+    }
+
+    pub fn if_else(condition: Expr, then: Expr, else_: Option<Expr>, span: Span) -> Self {
+        Expr::new(ExprKind::IfElse {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            else_: else_.map(Box::new),
+        }, span)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Param {
+    pub ident: Identifier,
+    pub ty: Type,
+}
+
+impl Param {
+    pub fn new(ident: Identifier, ty: Type) -> Self {
+        Param { ident, ty }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Type {
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    String,
+    Unit,
+    /// A type the checker hasn't inferred yet.
+    Var(usize),
+    Unknown,
+}
+
+impl Type {
+    /// The type named by an annotation, e.g. `i64` in `let x: i64 = 5;`.
+    pub fn from_name(name: &str) -> Option<Type> {
+        Some(match name {
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "bool" => Type::Bool,
+            "string" => Type::String,
+            _ => return None,
+        })
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::I32 | Type::I64 | Type::F32 | Type::F64)
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::I32 | Type::I64)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F32 | Type::F64)
+    }
+
+    /// Whether a value of this type can be used where `other` is expected
+    /// without losing its meaning, e.g. an `i32` as an `f64`.
+    pub fn widens_to(self, other: Type) -> bool {
+        self == other || matches!(
+            (self, other),
+            (Type::I32, Type::I64 | Type::F32 | Type::F64) | (Type::I64 | Type::F32, Type::F64)
+        )
+    }
+
+    /// The type both numeric operands of a binary operator are converted to.
+    pub fn wider(self, other: Type) -> Type {
+        if self.widens_to(other) {
+            other
+        } else if other.widens_to(self) {
+            self
+        } else {
+            // Neither `i64` nor `f32` holds the other.
+            Type::F64
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Unit => write!(f, "unit"),
+            Type::Var(_) | Type::Unknown => write!(f, "unknown"),
+        }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum LiteralExpr {
-    Number(i32),
+    Number(i64),
+    Float(f64),
     String(String),
     True,
     False,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum BinaryOperator {
     Equal,
     BangEqual,
@@ -138,14 +337,18 @@ pub enum BinaryOperator {
     Add,
     Divide,
     Multiply,
+    Remainder,
+    And,
+    Or,
 }
 
 impl BinaryOperator {
-    pub fn from_token(token_type: &TokenType) -> ParseResult<BinaryOperator> {
-        Ok(match token_type {
+    pub fn from_token(token: &Token) -> ParseResult<BinaryOperator> {
+        Ok(match token.token_type {
             TokenType::Minus => BinaryOperator::Subtract,
             TokenType::Plus => BinaryOperator::Add,
             TokenType::Star => BinaryOperator::Multiply,
+            TokenType::Percent => BinaryOperator::Remainder,
             TokenType::Slash => BinaryOperator::Divide,
             TokenType::BangEqual => BinaryOperator::BangEqual,
             TokenType::Equal => BinaryOperator::Equal,
@@ -154,23 +357,55 @@ impl BinaryOperator {
             TokenType::LessThanEqual => BinaryOperator::LessThanEqual,
             TokenType::GreaterThan => BinaryOperator::GreaterThan,
             TokenType::GreaterThanEqual => BinaryOperator::GreaterThanEqual,
-            _ => return Err(ParserError::ExpectedBinaryOperator(token_type.clone())),
+            TokenType::And => BinaryOperator::And,
+            TokenType::Or => BinaryOperator::Or,
+            _ => return Err(ParserError::ExpectedBinaryOperator(token.token_type.clone(), token.span)),
         })
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum UnaryOperator {
     Negate,
     Not,
 }
 
 impl UnaryOperator {
-    pub fn from_token(token_type: &TokenType) -> ParseResult<UnaryOperator> {
-        Ok(match token_type {
+    pub fn from_token(token: &Token) -> ParseResult<UnaryOperator> {
+        Ok(match token.token_type {
             TokenType::Minus => UnaryOperator::Negate,
             TokenType::Bang => UnaryOperator::Not,
-            _ => return Err(ParserError::ExpectedUnaryOperator(token_type.clone())),
+            _ => return Err(ParserError::ExpectedUnaryOperator(token.token_type.clone(), token.span)),
         })
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Equal => "==",
+            BinaryOperator::BangEqual => "!=",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::GreaterThanEqual => ">=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::LessThanEqual => "<=",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Remainder => "%",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOperator::Negate => write!(f, "-"),
+            UnaryOperator::Not => write!(f, "!"),
+        }
+    }
+}
@@ -1,23 +1,126 @@
-use crate::syntax::token::TokenType;
+use std::fmt;
+use crate::syntax::token::{Span, TokenType};
 
 pub type LexResult<T> = Result<T, SyntaxError>;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum SyntaxError {
-    UnexpectedEOF,
-    UnexpectedChar,
-    UnterminatedString,
+    UnexpectedEOF(Span),
+    UnexpectedChar(char, Span),
+    UnterminatedString(Span),
+    InvalidNumber(Span),
+    InconsistentIndentation(Span),
 }
 
 pub type ParseResult<T> = Result<T, ParserError>;
 
-// TODO: Use Token not TokenType.
 #[derive(Debug)]
 pub enum ParserError {
-    Expected(TokenType, TokenType, usize),
-    Unexpected(TokenType),
-    ExpectedPrimary(TokenType),
-    ExpectedUnaryOperator(TokenType),
-    ExpectedBinaryOperator(TokenType),
-    UnexpectedEOF,
+    Expected(TokenType, TokenType, Span),
+    Unexpected(TokenType, Span),
+    ExpectedPrimary(TokenType, Span),
+    ExpectedUnaryOperator(TokenType, Span),
+    ExpectedBinaryOperator(TokenType, Span),
+    InvalidNumber(Span),
+    UnknownType(String, Span),
+    /// A parameter of an extern function without a type.
+    MissingType(String, Span),
+    /// A `let` without `=`, variables have no value before they're assigned.
+    MissingInitializer(String, Span),
+    UnexpectedEOF(Span),
+    /// An error the lexer reported, kept with the parser's so they can be
+    /// reported in the order they appear.
+    Lexical(SyntaxError),
+}
+
+impl SyntaxError {
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxError::UnexpectedEOF(span)
+            | SyntaxError::UnexpectedChar(_, span)
+            | SyntaxError::UnterminatedString(span)
+            | SyntaxError::InvalidNumber(span)
+            | SyntaxError::InconsistentIndentation(span) => *span,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            SyntaxError::UnexpectedEOF(_) => "unexpected end of file".to_string(),
+            SyntaxError::UnexpectedChar(c, _) => format!("unexpected character '{}'", c),
+            SyntaxError::UnterminatedString(_) => "unterminated string".to_string(),
+            SyntaxError::InvalidNumber(_) => "invalid number".to_string(),
+            SyntaxError::InconsistentIndentation(_) => "inconsistent indentation".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.span().line, self.message())
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl ParserError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::Expected(_, _, span)
+            | ParserError::Unexpected(_, span)
+            | ParserError::ExpectedPrimary(_, span)
+            | ParserError::ExpectedUnaryOperator(_, span)
+            | ParserError::ExpectedBinaryOperator(_, span)
+            | ParserError::InvalidNumber(span)
+            | ParserError::UnknownType(_, span)
+            | ParserError::MissingType(_, span)
+            | ParserError::MissingInitializer(_, span)
+            | ParserError::UnexpectedEOF(span) => *span,
+            ParserError::Lexical(e) => e.span(),
+        }
+    }
+
+    /// The token the parser ran into, if the error is about one.
+    pub fn found(&self) -> Option<&TokenType> {
+        match self {
+            ParserError::Expected(_, found, _)
+            | ParserError::Unexpected(found, _)
+            | ParserError::ExpectedPrimary(found, _)
+            | ParserError::ExpectedUnaryOperator(found, _)
+            | ParserError::ExpectedBinaryOperator(found, _) => Some(found),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ParserError::Expected(expected, found, _) => format!("expected {} but found {}", expected, found),
+            ParserError::Unexpected(found, _) => format!("unexpected {}", found),
+            ParserError::ExpectedPrimary(found, _) => format!("expected expression but found {}", found),
+            ParserError::ExpectedUnaryOperator(found, _) => format!("expected unary operator but found {}", found),
+            ParserError::ExpectedBinaryOperator(found, _) => format!("expected binary operator but found {}", found),
+            ParserError::InvalidNumber(_) => "invalid number".to_string(),
+            ParserError::UnknownType(name, _) => format!("unknown type '{}'", name),
+            ParserError::MissingType(name, _) => {
+                format!("parameter '{}' of an extern function needs a type", name)
+            }
+            ParserError::MissingInitializer(name, _) => format!("variable '{}' needs an initial value", name),
+            ParserError::UnexpectedEOF(_) => "unexpected end of file".to_string(),
+            ParserError::Lexical(e) => e.message(),
+        }
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.span().line, self.message())
+    }
+}
+
+impl std::error::Error for ParserError {}
+
+impl From<SyntaxError> for ParserError {
+    fn from(e: SyntaxError) -> Self {
+        ParserError::Lexical(e)
+    }
 }
@@ -1,7 +1,7 @@
 use crate::syntax::ast::{BinaryOperator, Expr, UnaryOperator};
 use crate::syntax::error::{ParserError, ParseResult};
 use crate::syntax::parser::Parser;
-use crate::syntax::token::TokenType;
+use crate::syntax::token::{Token, TokenType};
 
 #[derive(PartialEq, PartialOrd)]
 enum Precedence {
@@ -29,13 +29,15 @@ impl From<&TokenType> for Precedence {
     fn from(token: &TokenType) -> Precedence {
         match token {
             TokenType::Equal => Precedence::Assign,
+            TokenType::Or => Precedence::Or,
+            TokenType::And => Precedence::And,
             TokenType::BangEqual | TokenType::EqualEqual => Precedence::Equality,
             TokenType::LessThan
             | TokenType::LessThanEqual
             | TokenType::GreaterThan
             | TokenType::GreaterThanEqual => Precedence::Comparison,
             TokenType::Plus | TokenType::Minus => Precedence::Term,
-            TokenType::Star | TokenType::Slash => Precedence::Factor,
+            TokenType::Star | TokenType::Slash | TokenType::Percent => Precedence::Factor,
             TokenType::Bang => Precedence::Unary,
             TokenType::LeftParen => Precedence::Call,
             TokenType::Dot => Precedence::Call,
@@ -65,8 +67,10 @@ fn parse_prefix(parser: &mut Parser) -> ParseResult<Expr> {
         TokenType::Number | TokenType::Identifier | TokenType::String | TokenType::True | TokenType::False => {
             parse_primary(parser)
         }
+        TokenType::InterpolationStart => parse_interpolation(parser),
         TokenType::Bang | TokenType::Minus => parse_unary(parser),
-        _ => Err(ParserError::Unexpected(parser.peek_type()?.clone())),
+        TokenType::LeftParen => parse_grouping(parser),
+        _ => Err(unexpected(parser)?),
     }
 }
 
@@ -81,51 +85,171 @@ fn parse_infix(parser: &mut Parser, left: Expr) -> ParseResult<Expr> {
         | TokenType::Plus
         | TokenType::Minus
         | TokenType::Star
-        | TokenType::Slash => parse_binary(parser, left),
+        | TokenType::Percent
+        | TokenType::Slash
+        | TokenType::And
+        | TokenType::Or => parse_binary(parser, left),
         TokenType::LeftParen => parse_call(parser, left),
-        _ => Err(ParserError::Unexpected(parser.peek_type()?.clone())),
+        _ => Err(unexpected(parser)?),
     }
 }
 
+fn unexpected(parser: &Parser) -> ParseResult<ParserError> {
+    let token = parser.peek()?;
+    Ok(ParserError::Unexpected(token.token_type.clone(), token.span))
+}
+
 fn parse_primary(parser: &mut Parser) -> ParseResult<Expr> {
     let token = parser.consume()?;
+    let span = token.span;
     match token.token_type {
-        TokenType::Number => Ok(Expr::number(token.source.parse::<i32>().unwrap())),
-        TokenType::String => Ok(Expr::string(token.source.to_string())),
-        TokenType::True => Ok(Expr::true_()),
-        TokenType::False => Ok(Expr::false_()),
+        TokenType::Number if token.source.contains('.') => token.source.parse::<f64>()
+            .map(|n| Expr::float(n, span))
+            .map_err(|_| ParserError::InvalidNumber(span)),
+        TokenType::Number => token.source.parse::<i64>()
+            .map(|n| Expr::number(n, span))
+            .map_err(|_| ParserError::InvalidNumber(span)),
+        TokenType::String => Ok(Expr::string(unescape(token.source), span)),
+        TokenType::True => Ok(Expr::true_(span)),
+        TokenType::False => Ok(Expr::false_(span)),
         TokenType::Identifier => {
             let ident = token.source.to_string();
 
+            if parser.is_function(&ident) && !parser.check(TokenType::LeftParen)? {
+                let args = parse_bare_args(parser)?;
+                return Ok(Expr::call(Expr::let_get(ident, span), args, parser.span_from(span)));
+            }
+
             Ok(if parser.match_(TokenType::Equal)? {
                 let expr = parser.expression()?;
-                Expr::let_set(ident, expr)
+                Expr::let_set(ident, expr, parser.span_from(span))
             } else {
-                Expr::let_get(ident)
+                Expr::let_get(ident, span)
             })
         }
-        _ => Err(ParserError::ExpectedPrimary(token.token_type.clone())),
+        _ => Err(ParserError::ExpectedPrimary(token.token_type.clone(), span)),
+    }
+}
+
+/// Parses the arguments of a call without parentheses, e.g. `hi "Bob"`.
+fn parse_bare_args(parser: &mut Parser) -> ParseResult<Vec<Expr>> {
+    let mut args = vec![];
+
+    let starts_arg = matches!(
+        parser.peek_type()?,
+        TokenType::Number
+            | TokenType::String
+            | TokenType::InterpolationStart
+            | TokenType::Identifier
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Bang
+    );
+    if !starts_arg {
+        return Ok(args);
+    }
+
+    loop {
+        args.push(parser.expression()?);
+
+        if !parser.match_(TokenType::Comma)? {
+            break;
+        }
+    }
+
+    Ok(args)
+}
+
+fn parse_interpolation(parser: &mut Parser) -> ParseResult<Expr> {
+    let start = parser.expect(TokenType::InterpolationStart)?;
+    let span = start.span;
+
+    let mut parts = vec![];
+    push_string_part(&mut parts, &start);
+
+    loop {
+        parts.push(parser.expression()?);
+
+        if parser.check(TokenType::InterpolationMid)? {
+            let mid = parser.consume()?;
+            push_string_part(&mut parts, &mid);
+        } else {
+            let end = parser.expect(TokenType::InterpolationEnd)?;
+            push_string_part(&mut parts, &end);
+            break;
+        }
     }
+
+    Ok(Expr::interpolation(parts, parser.span_from(span)))
+}
+
+fn push_string_part(parts: &mut Vec<Expr>, token: &Token) {
+    if !token.source.is_empty() {
+        parts.push(Expr::string(unescape(token.source), token.span));
+    }
+}
+
+/// Replaces the escape sequences in a string literal, unknown escapes are kept
+/// as they are.
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some(c @ ('\\' | '"' | '$')) => result.push(c),
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+
+    result
 }
 
 fn parse_binary(parser: &mut Parser, left: Expr) -> ParseResult<Expr> {
     let op_token = parser.consume()?;
     let precedence = Precedence::from(&op_token.token_type);
-    let op = BinaryOperator::from_token(&op_token.token_type)?;
+    let op = BinaryOperator::from_token(&op_token)?;
     let right = parse_expr(parser, precedence)?;
 
-    Ok(Expr::binary(left, op, right))
+    let span = left.span.to(right.span);
+    Ok(Expr::binary(left, op, right, span))
 }
 
 fn parse_unary(parser: &mut Parser) -> ParseResult<Expr> {
     let op_token = parser.consume()?;
-    let op = UnaryOperator::from_token(&op_token.token_type)?;
+    let op = UnaryOperator::from_token(&op_token)?;
     let right = parse_expr(parser, Precedence::Unary)?;
 
-    Ok(Expr::unary(op, right))
+    let span = op_token.span.to(right.span);
+    Ok(Expr::unary(op, right, span))
+}
+
+fn parse_grouping(parser: &mut Parser) -> ParseResult<Expr> {
+    let start = parser.peek()?.span;
+    parser.expect(TokenType::LeftParen)?;
+    let mut expr = parser.expression()?;
+    parser.expect(TokenType::RightParen)?;
+
+    // The parentheses are part of the expression.
+    expr.span = parser.span_from(start);
+    Ok(expr)
 }
 
 fn parse_call(parser: &mut Parser, left: Expr) -> ParseResult<Expr> {
+    let start = left.span;
     parser.expect(TokenType::LeftParen)?;
 
     let mut args = vec![];
@@ -139,5 +263,5 @@ fn parse_call(parser: &mut Parser, left: Expr) -> ParseResult<Expr> {
 
     parser.expect(TokenType::RightParen)?;
 
-    Ok(Expr::call(left, args))
+    Ok(Expr::call(left, args, parser.span_from(start)))
 }
\ No newline at end of file
@@ -1,12 +1,19 @@
+use std::collections::VecDeque;
 use std::iter::Peekable;
 use std::str::CharIndices;
 use crate::syntax::error::{LexResult, SyntaxError};
-use crate::syntax::token::{Position, Token, TokenType, ToKeyword};
+use crate::syntax::token::{Span, Token, TokenType, ToKeyword};
 
 pub struct Lexer<'a> {
     source: &'a str,
     chars: Peekable<CharIndices<'a>>,
     line: usize,
+    indents: Vec<usize>,
+    pending: VecDeque<Token<'a>>,
+    nesting: usize,
+    interpolations: Vec<usize>,
+    line_start: bool,
+    errors: Vec<SyntaxError>,
 }
 
 impl<'a> Lexer<'a> {
@@ -15,10 +22,32 @@ impl<'a> Lexer<'a> {
             source,
             chars: source.char_indices().peekable(),
             line: 1,
+            indents: vec![0],
+            pending: VecDeque::new(),
+            nesting: 0,
+            interpolations: vec![],
+            line_start: true,
+            errors: vec![],
         }
     }
 
+    pub fn into_errors(self) -> Vec<SyntaxError> {
+        self.errors
+    }
+
     pub fn read_token(&mut self) -> LexResult<Option<Token<'a>>> {
+        if let Some(token) = self.pending.pop_front() {
+            return Ok(Some(token));
+        }
+
+        // Indentation is only significant outside of brackets.
+        if self.line_start && self.nesting == 0 {
+            self.line_start = false;
+            if let Some(token) = self.indentation()? {
+                return Ok(Some(token));
+            }
+        }
+
         self.skip_whitespace()?;
         if self.is_at_end() {
             return self.eof();
@@ -34,17 +63,51 @@ impl<'a> Lexer<'a> {
         }
 
         let token_type = match c {
-            '(' => TokenType::LeftParen,
-            ')' => TokenType::RightParen,
-            '[' => TokenType::LeftBracket,
-            ']' => TokenType::RightBracket,
-            '{' => TokenType::LeftBrace,
-            '}' => TokenType::RightBrace,
+            '(' | '[' | '{' => {
+                self.nesting += 1;
+                match c {
+                    '(' => TokenType::LeftParen,
+                    '[' => TokenType::LeftBracket,
+                    _ => TokenType::LeftBrace,
+                }
+            }
+            '}' if self.interpolations.last() == Some(&self.nesting) => {
+                // End of an embedded `${expr}`, continue with the string.
+                self.interpolations.pop();
+                self.nesting -= 1;
+                return self.string_or_error(start, false);
+            }
+            ')' | ']' | '}' => {
+                self.nesting = self.nesting.saturating_sub(1);
+                match c {
+                    ')' => TokenType::RightParen,
+                    ']' => TokenType::RightBracket,
+                    _ => TokenType::RightBrace,
+                }
+            }
+            '\n' => {
+                self.line_start = true;
+                let span = self.span(start, start + 1, self.line - 1);
+                return Ok(Some(Token::new(TokenType::Newline, "", span)));
+            }
             ',' => TokenType::Comma,
-            '.' => TokenType::Dot,
+            '.' => {
+                if self.match_('.')? {
+                    TokenType::DotDot
+                } else {
+                    TokenType::Dot
+                }
+            }
             '+' => TokenType::Plus,
-            '-' => TokenType::Minus,
+            '-' => {
+                if self.match_('>')? {
+                    TokenType::Arrow
+                } else {
+                    TokenType::Minus
+                }
+            }
             '*' => TokenType::Star,
+            '%' => TokenType::Percent,
             '/' => {
                 // Ignore comments.
                 if self.check('/')? {
@@ -54,6 +117,8 @@ impl<'a> Lexer<'a> {
                     TokenType::Slash
                 }
             }
+            '&' if self.match_('&')? => TokenType::And,
+            '|' if self.match_('|')? => TokenType::Or,
             '!' => {
                 if self.match_('=')? {
                     TokenType::BangEqual
@@ -83,9 +148,11 @@ impl<'a> Lexer<'a> {
                 }
             }
             ';' => TokenType::Semicolon,
-            '"' => return self.string(start),
+            ':' => TokenType::Colon,
+            '"' => return self.string_or_error(start, true),
             _ => {
-                return Err(SyntaxError::UnexpectedChar);
+                let span = self.span(start, start + c.len_utf8(), self.line);
+                return Ok(Some(self.error_token(SyntaxError::UnexpectedChar(c, span), start)));
             }
         };
 
@@ -115,51 +182,208 @@ impl<'a> Lexer<'a> {
             }
         }
 
+        // A number running into letters, e.g. `12ab`.
+        if self.peek().is_some_and(|c| c.is_alphabetic()) {
+            self.advance_while(|c| c.is_alphanumeric())?;
+            let end = self.offset();
+            let span = self.span(start, end, self.line);
+            return Ok(Some(self.error_token(SyntaxError::InvalidNumber(span), start)));
+        }
+
         self.make_token(TokenType::Number, start)
     }
 
-    fn string(&mut self, start: usize) -> LexResult<Option<Token<'a>>> {
-        let start = start + 1;
-        self.advance_while(|&c| c != '"')?;
-        if self.is_at_end() {
-            return Err(SyntaxError::UnterminatedString);
+    /// Lexes a string literal, or the rest of one after an interpolation,
+    /// starting at the `"` or `}` at `start`. An unterminated string becomes
+    /// an error token.
+    fn string_or_error(&mut self, start: usize, first: bool) -> LexResult<Option<Token<'a>>> {
+        if let Err(error) = self.string_part(start + 1, first) {
+            let token = self.error_token(error, start);
+            self.pending.push_back(token);
         }
+        Ok(self.pending.pop_front())
+    }
 
-        // Consume the '"'.
-        self.advance()?;
+    /// Lexes the remainder of a string literal starting at `start`. A string
+    /// containing `$name` or `${expr}` is split into interpolation tokens with
+    /// the tokens of the embedded expressions in between.
+    fn string_part(&mut self, start: usize, first: bool) -> LexResult<()> {
+        let line = self.line;
+        loop {
+            let end = self.offset();
+            match self.peek() {
+                None => return Err(SyntaxError::UnterminatedString(self.span(start, end, line))),
+                Some('"') => {
+                    let token_type = if first { TokenType::String } else { TokenType::InterpolationEnd };
+                    self.push_string_token(token_type, start, end, line);
+
+                    // Consume the '"'.
+                    self.advance()?;
+                    return Ok(());
+                }
+                Some('$') => match self.peek_next() {
+                    Some('{') => {
+                        self.push_interpolation(start, end, line, first);
+                        self.advance()?;
+                        self.advance()?;
 
-        let source = self.token_contents(start);
-        let s = &source[0..source.len() - 1];
+                        // The string continues at the matching '}'.
+                        self.nesting += 1;
+                        self.interpolations.push(self.nesting);
+                        return Ok(());
+                    }
+                    Some(c) if c.is_alphabetic() => {
+                        self.push_interpolation(start, end, line, first);
+                        self.advance()?;
+
+                        let (ident_start, _) = self.advance()?;
+                        let ident = self.identifier(ident_start)?.unwrap();
+                        self.pending.push_back(ident);
+
+                        let rest = self.offset();
+                        return self.string_part(rest, false);
+                    }
+                    _ => {
+                        self.advance()?;
+                    }
+                },
+                Some('\\') => {
+                    // Skip the escaped character, it can't end the string or
+                    // start an interpolation.
+                    self.advance()?;
+                    if !self.is_at_end() {
+                        self.advance()?;
+                    }
+                }
+                Some(_) => {
+                    self.advance()?;
+                }
+            }
+        }
+    }
+
+    fn push_interpolation(&mut self, start: usize, end: usize, line: usize, first: bool) {
+        let token_type = if first { TokenType::InterpolationStart } else { TokenType::InterpolationMid };
+        self.push_string_token(token_type, start, end, line);
+    }
+
+    /// Pushes the part of a string from `start`, which is on `line`, to
+    /// `end`.
+    fn push_string_token(&mut self, token_type: TokenType, start: usize, end: usize, line: usize) {
         let token = Token::new(
-            TokenType::String,
-            s,
-            Position::new(start, start + s.len(), self.line),
+            token_type,
+            &self.source[start..end],
+            self.span(start, end, line),
         );
+        self.pending.push_back(token);
+    }
+
+    /// Measures the indentation of a new line. Emits an `Indent` when the line
+    /// is indented deeper than the current block and one `Dedent` for every
+    /// block it closes.
+    fn indentation(&mut self) -> LexResult<Option<Token<'a>>> {
+        let width = loop {
+            let width = self.advance_while(|&c| c == ' ' || c == '\t')?;
+            match self.peek() {
+                // Blank and comment-only lines don't affect the layout.
+                Some('\n') | Some('\r') => {
+                    self.advance()?;
+                }
+                Some('/') if self.peek_next() == Some('/') => {
+                    self.advance_while(|&c| c != '\n')?;
+                }
+                None => return Ok(None),
+                _ => break width,
+            }
+        };
+
+        let start = self.offset();
+        if width > self.current_indent() {
+            self.indents.push(width);
+            return Ok(Some(self.layout_token(TokenType::Indent, start)));
+        }
+
+        while width < self.current_indent() {
+            self.indents.pop();
+            let dedent = self.layout_token(TokenType::Dedent, start);
+            self.pending.push_back(dedent);
+        }
 
-        Ok(Some(token))
+        if width != self.current_indent() {
+            let span = self.span(start - width, start, self.line);
+            self.errors.push(SyntaxError::InconsistentIndentation(span));
+        }
+
+        Ok(self.pending.pop_front())
+    }
+
+    fn current_indent(&self) -> usize {
+        *self.indents.last().unwrap()
+    }
+
+    fn layout_token(&self, token_type: TokenType, start: usize) -> Token<'a> {
+        Token::new(token_type, "", self.span(start, start, self.line))
     }
 
     fn eof(&mut self) -> LexResult<Option<Token<'a>>> {
-        self.make_token(TokenType::EOF, self.source.len())
+        // Close the blocks that are still open at the end of the file.
+        let end = self.source.len();
+        while self.indents.len() > 1 {
+            self.indents.pop();
+            let dedent = self.layout_token(TokenType::Dedent, end);
+            self.pending.push_back(dedent);
+        }
+
+        let eof = self.make_token(TokenType::EOF, end)?.unwrap();
+        self.pending.push_back(eof);
+
+        Ok(self.pending.pop_front())
     }
 
     fn make_token(&mut self, token_type: TokenType, start: usize) -> LexResult<Option<Token<'a>>> {
         let source = self.token_contents(start);
-        let pos = Position::new(start, start + source.len(), self.line);
-        Ok(Some(Token::new(token_type, source, pos)))
+        let span = self.span(start, start + source.len(), self.line);
+        Ok(Some(Token::new(token_type, source, span)))
+    }
+
+    /// Records `error` and returns a token covering the source from `start`
+    /// to here, so lexing can carry on.
+    fn error_token(&mut self, error: SyntaxError, start: usize) -> Token<'a> {
+        let end = self.offset();
+        let span = self.span(start, end, error.span().line);
+        self.errors.push(error);
+        Token::new(TokenType::Error, &self.source[start..end], span)
+    }
+
+    /// The span from `start` to `end` on `line`, the column is counted in
+    /// characters.
+    fn span(&self, start: usize, end: usize, line: usize) -> Span {
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let column = self.source[line_start..start].chars().count() + 1;
+        Span::new(start, end, line, column)
+    }
+
+    fn eof_span(&self) -> Span {
+        let end = self.source.len();
+        self.span(end, end, self.line)
     }
 
     fn token_contents(&mut self, start: usize) -> &'a str {
-        let end = self
-            .chars
+        let end = self.offset();
+        self.source[start..end].trim_end()
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
             .peek()
             .map(|&(i, _)| i)
-            .unwrap_or(self.source.len());
-        &self.source[start..end].trim_end()
+            .unwrap_or(self.source.len())
     }
 
     fn skip_whitespace(&mut self) -> LexResult<()> {
-        self.advance_while(|&c| c == ' ' || c == '\t' || c == '\n' || c == '\r')?;
+        // Line breaks inside brackets don't end a statement.
+        let nesting = self.nesting;
+        self.advance_while(|&c| c == ' ' || c == '\t' || c == '\r' || (c == '\n' && nesting > 0))?;
         Ok(())
     }
 
@@ -178,15 +402,14 @@ impl<'a> Lexer<'a> {
     }
 
     fn advance(&mut self) -> LexResult<(usize, char)> {
-        self.chars
+        let (current, c) = self.chars
             .next()
-            .map(|(current, c)| {
-                if c == '\n' {
-                    self.line += 1;
-                }
-                (current, c)
-            })
-            .ok_or(SyntaxError::UnexpectedEOF)
+            .ok_or_else(|| SyntaxError::UnexpectedEOF(self.eof_span()))?;
+
+        if c == '\n' {
+            self.line += 1;
+        }
+        Ok((current, c))
     }
 
     // TODO: Rewrite.
@@ -201,13 +424,13 @@ impl<'a> Lexer<'a> {
     }
 
     fn check(&mut self, c: char) -> LexResult<bool> {
-        self.peek()
-            .map(|p| p == c)
-            .ok_or(SyntaxError::UnexpectedEOF)
+        Ok(self.peek() == Some(c))
     }
 
     fn peek_next(&mut self) -> Option<char> {
-        self.chars.nth(1).map(|(_, c)| c)
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next().map(|(_, c)| c)
     }
 
     fn peek(&mut self) -> Option<char> {
@@ -222,49 +445,162 @@ impl<'a> Lexer<'a> {
 #[cfg(test)]
 mod tests {
     use crate::syntax::lex;
+    use crate::syntax::error::SyntaxError;
     use crate::syntax::token::*;
     use crate::syntax::token::TokenType::*;
 
+    fn lex_ok(source: &str) -> Vec<Token<'_>> {
+        let (tokens, errors) = lex(source);
+        assert!(errors.is_empty());
+        tokens
+    }
+
     #[test]
     fn lex_numbers() {
         let expect = vec![
-            Token::new(Number, "2", Position::new(0, 1, 1)),
-            Token::new(Number, "10", Position::new(2, 4, 1)),
-            Token::new(Number, "3.33", Position::new(5, 9, 1)),
-            Token::new(EOF, "", Position::new(9, 9, 1)),
+            Token::new(Number, "2", Span::new(0, 1, 1, 1)),
+            Token::new(Number, "10", Span::new(2, 4, 1, 3)),
+            Token::new(Number, "3.33", Span::new(5, 9, 1, 6)),
+            Token::new(EOF, "", Span::new(9, 9, 1, 10)),
         ];
 
         let source = r#"2 10 3.33"#;
 
-        let actual = lex(source).unwrap();
+        let actual = lex_ok(source);
         assert_eq!(expect, actual);
     }
 
     #[test]
     fn lex_strings() {
         let expect = vec![
-            Token::new(String, "Hello", Position::new(1, 6, 1)),
-            Token::new(String, ",", Position::new(9, 10, 1)),
-            Token::new(String, "World!", Position::new(13, 19, 1)),
-            Token::new(EOF, "", Position::new(20, 20, 1)),
+            Token::new(String, "Hello", Span::new(1, 6, 1, 2)),
+            Token::new(String, ",", Span::new(9, 10, 1, 10)),
+            Token::new(String, "World!", Span::new(13, 19, 1, 14)),
+            Token::new(EOF, "", Span::new(20, 20, 1, 21)),
         ];
 
         let source = r#""Hello" "," "World!""#;
 
-        let actual = lex(source).unwrap();
+        let actual = lex_ok(source);
         assert_eq!(expect, actual);
     }
 
     #[test]
     fn lex_comments() {
         let expect = vec![
-            Token::new(Number, "2", Position::new(0, 1, 1)),
-            Token::new(EOF, "", Position::new(34, 34, 1)),
+            Token::new(Number, "2", Span::new(0, 1, 1, 1)),
+            Token::new(EOF, "", Span::new(34, 34, 1, 35)),
         ];
 
         let source = r#"2 // This comment will be ignored."#;
 
-        let actual = lex(source).unwrap();
+        let actual = lex_ok(source);
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn lex_layout() {
+        let source = "double x:\n    x * 2\nprint 1";
+
+        let actual: Vec<TokenType> = lex_ok(source)
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect();
+
+        let expect = vec![
+            Identifier, Identifier, Colon, Newline,
+            Indent, Identifier, Star, Number, Newline,
+            Dedent, Print, Number, EOF,
+        ];
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn lex_layout_ignores_brackets_and_blank_lines() {
+        let source = "f(1,\n    2)\n\n    // comment\ng:\n  1\n";
+
+        let actual: Vec<TokenType> = lex_ok(source)
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect();
+
+        let expect = vec![
+            Identifier, LeftParen, Number, Comma, Number, RightParen, Newline,
+            Identifier, Colon, Newline,
+            Indent, Number, Newline,
+            Dedent, EOF,
+        ];
         assert_eq!(expect, actual);
     }
+
+    #[test]
+    fn lex_interpolation() {
+        let expect = vec![
+            Token::new(InterpolationStart, "a ", Span::new(1, 3, 1, 2)),
+            Token::new(Identifier, "x", Span::new(4, 5, 1, 5)),
+            Token::new(InterpolationMid, " b ", Span::new(5, 8, 1, 6)),
+            Token::new(Identifier, "y", Span::new(10, 11, 1, 11)),
+            Token::new(Plus, "+", Span::new(12, 13, 1, 13)),
+            Token::new(Number, "1", Span::new(14, 15, 1, 15)),
+            Token::new(InterpolationEnd, "", Span::new(16, 16, 1, 17)),
+            Token::new(EOF, "", Span::new(17, 17, 1, 18)),
+        ];
+
+        let source = r#""a $x b ${y + 1}""#;
+
+        let actual = lex_ok(source);
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn lex_columns() {
+        let tokens = lex_ok("let a = 1\nprint a");
+
+        assert_eq!(tokens[3].span, Span::new(8, 9, 1, 9));
+        assert_eq!(tokens[6].span, Span::new(16, 17, 2, 7));
+    }
+
+    #[test]
+    fn lex_multiline_strings_at_their_start() {
+        let tokens = lex_ok("print \"a\nb\"\nprint \"c\n${1}\"");
+
+        assert_eq!(tokens[1], Token::new(String, "a\nb", Span::new(7, 10, 1, 8)));
+        assert_eq!(tokens[4], Token::new(InterpolationStart, "c\n", Span::new(19, 21, 3, 8)));
+    }
+
+    #[test]
+    fn lex_unexpected_char() {
+        let (tokens, errors) = lex("1\nx @ 2");
+
+        assert_eq!(errors, vec![SyntaxError::UnexpectedChar('@', Span::new(4, 5, 2, 3))]);
+        assert_eq!(tokens[3], Token::new(Error, "@", Span::new(4, 5, 2, 3)));
+        assert_eq!(tokens[4].token_type, Number);
+    }
+
+    #[test]
+    fn lex_keeps_going_after_errors() {
+        let (tokens, errors) = lex("let a = 12ab\nprint # a\nprint \"oops");
+        let types: Vec<TokenType> = tokens.iter().map(|t| t.token_type.clone()).collect();
+
+        assert_eq!(types, vec![
+            Let, Identifier, Equal, Error, Newline,
+            Print, Error, Identifier, Newline,
+            Print, Error, EOF,
+        ]);
+        assert_eq!(tokens[3].source, "12ab");
+        assert_eq!(tokens[10].source, "\"oops");
+        assert_eq!(errors, vec![
+            SyntaxError::InvalidNumber(Span::new(8, 12, 1, 9)),
+            SyntaxError::UnexpectedChar('#', Span::new(19, 20, 2, 7)),
+            SyntaxError::UnterminatedString(Span::new(30, 34, 3, 8)),
+        ]);
+    }
+
+    #[test]
+    fn lex_inconsistent_indentation() {
+        let (tokens, errors) = lex("f:\n    1\n  2\n");
+
+        assert_eq!(errors, vec![SyntaxError::InconsistentIndentation(Span::new(9, 11, 3, 1))]);
+        assert_eq!(tokens.last().unwrap().token_type, EOF);
+    }
 }
@@ -1,8 +1,7 @@
-use crate::syntax::ast::Program;
-use crate::syntax::error::{LexResult, ParseResult};
+use crate::syntax::ast::{Identifier, Program};
 use crate::syntax::lexer::Lexer;
 use crate::syntax::parser::Parser;
-use crate::syntax::token::{Token, TokenType};
+use crate::syntax::token::Token;
 
 mod token;
 mod lexer;
@@ -11,30 +10,55 @@ mod expr_parser;
 mod error;
 pub mod ast;
 
-pub fn parse<'a>(tokens: &'a mut Vec<Token<'a>>) -> ParseResult<Program> {
+pub use crate::syntax::error::{ParserError, SyntaxError};
+pub use crate::syntax::token::{Span, TokenType};
+
+/// Parses as much of the program as it can. Declarations with syntax errors
+/// are left out of the program and their errors returned alongside it.
+/// `functions` are declared outside the tokens and can be called without
+/// parentheses too.
+pub fn parse<'a>(tokens: &'a mut Vec<Token<'a>>, functions: &[Identifier]) -> (Program, Vec<ParserError>) {
     let mut parser = Parser::new(tokens);
+    parser.declare_functions(functions);
 
     let mut ast = vec![];
-    while !parser.is_eof()? {
-        ast.push(parser.declaration()?);
+    loop {
+        if parser.skip_newlines().is_err() || parser.is_eof().unwrap_or(true) {
+            break;
+        }
+        if let Some(expr) = parser.declaration_or_synchronize() {
+            ast.push(expr);
+        }
     }
 
-    Ok(ast)
+    (ast, parser.into_errors())
 }
 
-pub fn lex(source: &str) -> LexResult<Vec<Token>> {
+/// Lexes the whole source. Anything the lexer can't make sense of becomes an
+/// error token and its error is returned alongside the tokens.
+pub fn lex(source: &str) -> (Vec<Token<'_>>, Vec<SyntaxError>) {
     let mut lexer = Lexer::new(source);
 
     let mut tokens = vec![];
-    loop {
-        if let Some(token) = lexer.read_token()? {
-            if let TokenType::EOF = token.token_type {
+    let fatal = loop {
+        match lexer.read_token() {
+            Ok(Some(token)) if token.token_type == TokenType::EOF => {
                 tokens.push(token);
-                break;
+                break None;
             }
-            tokens.push(token);
+            Ok(Some(token)) => tokens.push(token),
+            Ok(None) => {}
+            Err(error) => break Some(error),
         }
+    };
+
+    let mut errors = lexer.into_errors();
+    if let Some(error) = fatal {
+        // The lexer can't go on, end the tokens where it stopped.
+        let span = error.span();
+        tokens.push(Token::new(TokenType::EOF, "", Span::new(span.end, span.end, span.line, span.column)));
+        errors.push(error);
     }
 
-    Ok(tokens)
-}
\ No newline at end of file
+    (tokens, errors)
+}
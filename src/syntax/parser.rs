@@ -1,47 +1,138 @@
+use std::collections::HashSet;
 use crate::syntax::ast::*;
 use crate::syntax::error::{ParserError, ParseResult};
 use crate::syntax::expr_parser;
-use crate::syntax::token::{Token, TokenType};
+use crate::syntax::token::{Span, Token, TokenType};
 
 pub struct Parser<'a> {
     tokens: &'a mut Vec<Token<'a>>,
+    functions: HashSet<Identifier>,
+    /// The span of the last consumed token.
+    previous: Span,
+    /// The span of the `EOF` token, where running out of tokens is reported.
+    eof: Span,
+    errors: Vec<ParserError>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a mut Vec<Token<'a>>) -> Self {
+        let functions = function_names(tokens);
+        let eof = tokens.last().map_or(Span::default(), |t| t.span);
         tokens.reverse();
-        Parser { tokens }
+        Parser { tokens, functions, previous: Span::default(), eof, errors: vec![] }
     }
 
     pub fn declaration(&mut self) -> ParseResult<Expr> {
         match self.peek_type()? {
             TokenType::Let => self.parse_let(),
             TokenType::Fun => self.parse_fun(),
+            TokenType::Extern => self.parse_extern(),
             TokenType::Print => self.parse_print(),
             TokenType::If => self.parse_if(),
+            TokenType::Return => self.parse_return(),
+            TokenType::While => self.parse_while(),
+            TokenType::For => self.parse_for(),
+            TokenType::Break => {
+                let span = self.consume()?.span;
+                self.match_(TokenType::Semicolon)?;
+                Ok(Expr::break_(span))
+            }
+            TokenType::Continue => {
+                let span = self.consume()?.span;
+                self.match_(TokenType::Semicolon)?;
+                Ok(Expr::continue_(span))
+            }
             TokenType::LeftBrace => self.parse_block(),
+            TokenType::Identifier if self.is_layout_fun() => self.parse_layout_fun(),
             _ => self.parse_expr_statement(),
         }
     }
 
+    /// Parses a declaration. On a syntax error the error is recorded and the
+    /// tokens up to the start of the next statement are skipped.
+    pub fn declaration_or_synchronize(&mut self) -> Option<Expr> {
+        let remaining = self.tokens.len();
+        match self.declaration() {
+            Ok(expr) => Some(expr),
+            Err(error) => {
+                // The lexer already reported what's wrong with an error token.
+                if error.found() != Some(&TokenType::Error) {
+                    self.errors.push(error);
+                }
+                self.synchronize();
+
+                // Always make progress, e.g. past a stray `}`.
+                if self.tokens.len() == remaining && !self.is_eof().unwrap_or(true) {
+                    self.tokens.pop();
+                }
+                None
+            }
+        }
+    }
+
+    /// Skips tokens until the end of the current statement, a keyword that
+    /// starts a new one or the end of the enclosing block. Blocks opened by
+    /// the skipped statement are skipped as a whole.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.tokens.last() {
+            match token.token_type {
+                TokenType::EOF => return,
+                TokenType::LeftBrace | TokenType::Indent => depth += 1,
+                TokenType::RightBrace | TokenType::Dedent if depth == 0 => return,
+                TokenType::RightBrace | TokenType::Dedent => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.tokens.pop();
+                        return;
+                    }
+                }
+                TokenType::Semicolon | TokenType::Newline if depth == 0 => {
+                    self.tokens.pop();
+
+                    // A layout body on the following lines belongs to the
+                    // statement too.
+                    if self.check(TokenType::Indent).unwrap_or(false) {
+                        continue;
+                    }
+                    return;
+                }
+                TokenType::Let
+                | TokenType::Fun
+                | TokenType::Extern
+                | TokenType::Print
+                | TokenType::If
+                | TokenType::Return
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Break
+                | TokenType::Continue if depth == 0 => return,
+                _ => {}
+            }
+            self.tokens.pop();
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<ParserError> {
+        self.errors
+    }
+
     fn parse_let(&mut self) -> ParseResult<Expr> {
-        self.expect(TokenType::Let)?;
+        let start = self.expect(TokenType::Let)?.span;
 
         let ident = self.parse_identifier()?;
+        let ty = self.parse_annotation()?;
 
-        let initializer = if self.match_(TokenType::Equal)? {
-            self.parse_expr_statement()?
-        } else {
-            self.expect(TokenType::Semicolon)?;
-            Expr::number(4) // TODO: NIL.
-            // Expr::Literal(LiteralExpr::Nil)
-        };
+        if !self.match_(TokenType::Equal)? {
+            return Err(ParserError::MissingInitializer(ident, self.span_from(start)));
+        }
+        let initializer = self.parse_expr_statement()?;
 
-        Ok(Expr::let_assign(ident, initializer))
+        Ok(Expr::let_assign(ident, ty, initializer, self.span_from(start)))
     }
 
     fn parse_fun(&mut self) -> ParseResult<Expr> {
-        self.expect(TokenType::Fun)?;
+        let start = self.expect(TokenType::Fun)?.span;
 
         let name = self.parse_identifier()?;
 
@@ -50,52 +141,172 @@ impl<'a> Parser<'a> {
         let args = self.parse_args()?;
         self.expect(TokenType::RightParen)?;
 
-        self.expect(TokenType::LeftBrace)?;
+        let ret = if self.match_(TokenType::Arrow)? {
+            self.parse_type()?
+        } else {
+            Type::Unknown
+        };
+
+        let body = self.parse_body()?;
+
+        Ok(Expr::fun(name, args, ret, body, self.span_from(start)))
+    }
+
+    /// Parses the declaration of a function the host provides, e.g.
+    /// `extern fun now() -> i32;`. There is no body to infer its types from.
+    fn parse_extern(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(TokenType::Extern)?.span;
+        self.expect(TokenType::Fun)?;
+
+        let name = self.parse_identifier()?;
+
+        self.expect(TokenType::LeftParen)?;
+        let params = self.parse_args()?;
+        self.expect(TokenType::RightParen)?;
+
+        let ret = if self.match_(TokenType::Arrow)? {
+            self.parse_type()?
+        } else {
+            Type::Unit
+        };
+        self.match_(TokenType::Semicolon)?;
+
+        let span = self.span_from(start);
+        if let Some(param) = params.iter().find(|p| p.ty == Type::Unknown) {
+            return Err(ParserError::MissingType(param.ident.clone(), span));
+        }
 
-        let body = self.block()?;
+        Ok(Expr::extern_(name, params, ret, span))
+    }
+
+    /// Parses a function declared with the layout syntax, e.g. `hi name:`
+    /// followed by an indented body.
+    fn parse_layout_fun(&mut self) -> ParseResult<Expr> {
+        let start = self.peek()?.span;
+        let name = self.parse_identifier()?;
 
-        Ok(Expr::fun(name, args, body))
+        let mut params = vec![];
+        while self.check(TokenType::Identifier)? {
+            params.push(Param::new(self.parse_identifier()?, Type::Unknown));
+        }
+
+        let body = self.parse_body()?;
+
+        Ok(Expr::fun(name, params, Type::Unknown, body, self.span_from(start)))
     }
 
     fn parse_print(&mut self) -> ParseResult<Expr> {
-        self.expect(TokenType::Print)?;
+        let start = self.expect(TokenType::Print)?.span;
         let expr = self.parse_expr_statement()?;
-        Ok(Expr::print(expr))
+        Ok(Expr::print(expr, self.span_from(start)))
     }
 
     fn parse_if(&mut self) -> ParseResult<Expr> {
-        self.expect(TokenType::If)?;
+        let start = self.expect(TokenType::If)?.span;
 
         let condition = self.expression()?;
-        let then = self.declaration()?;
+        let then = self.parse_branch()?;
 
-        let else_ = if self.match_(TokenType::Else)? {
-            Some(self.declaration()?)
+        let else_ = if self.match_past_newlines(TokenType::Else)? {
+            Some(self.parse_branch()?)
         } else {
             None
         };
 
-        Ok(Expr::if_else(condition, then, else_))
+        Ok(Expr::if_else(condition, then, else_, self.span_from(start)))
+    }
+
+    fn parse_return(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(TokenType::Return)?.span;
+
+        let value = match self.peek_type()? {
+            TokenType::Semicolon
+            | TokenType::Newline
+            | TokenType::Dedent
+            | TokenType::RightBrace
+            | TokenType::EOF => None,
+            _ => Some(self.expression()?),
+        };
+        self.match_(TokenType::Semicolon)?;
+
+        Ok(Expr::return_(value, self.span_from(start)))
+    }
+
+    fn parse_while(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(TokenType::While)?.span;
+
+        let condition = self.expression()?;
+        let body = self.parse_branch()?;
+
+        Ok(Expr::while_(condition, body, self.span_from(start)))
+    }
+
+    fn parse_for(&mut self) -> ParseResult<Expr> {
+        let for_ = self.expect(TokenType::For)?.span;
+
+        let ident = self.parse_identifier()?;
+        self.expect(TokenType::In)?;
+        let start = self.expression()?;
+        self.expect(TokenType::DotDot)?;
+        let end = self.expression()?;
+        let body = self.parse_branch()?;
+
+        Ok(Expr::for_(ident, start, end, body, self.span_from(for_)))
     }
 
     fn parse_block(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(TokenType::LeftBrace)?.span;
+        let expressions = self.block()?;
+
+        Ok(Expr::block(expressions, self.span_from(start)))
+    }
+
+    fn parse_branch(&mut self) -> ParseResult<Expr> {
+        if self.check(TokenType::Colon)? {
+            let start = self.peek()?.span;
+            let body = self.parse_body()?;
+            return Ok(Expr::block(body, self.span_from(start)));
+        }
+
+        self.declaration()
+    }
+
+    /// Parses a body that is either wrapped in braces or introduced by a colon
+    /// and indented on the following lines.
+    fn parse_body(&mut self) -> ParseResult<BlockDecl> {
+        if self.match_(TokenType::Colon)? {
+            return self.layout_block();
+        }
+
         self.expect(TokenType::LeftBrace)?;
+        self.block()
+    }
 
-        let mut expressions = vec![];
+    fn layout_block(&mut self) -> ParseResult<BlockDecl> {
+        // The body can follow the colon on the same line, e.g. `five: 5`.
+        if !self.match_(TokenType::Newline)? {
+            return Ok(vec![self.declaration()?]);
+        }
 
-        while !self.check(TokenType::RightBrace)? && !self.check(TokenType::EOF)? {
-            expressions.push(self.declaration()?);
+        self.expect(TokenType::Indent)?;
+
+        let mut exprs = vec![];
+        while !self.check(TokenType::Dedent)? && !self.check(TokenType::EOF)? {
+            exprs.extend(self.declaration_or_synchronize());
+            self.skip_newlines()?;
         }
 
-        self.expect(TokenType::RightBrace)?;
+        self.expect(TokenType::Dedent)?;
 
-        Ok(Expr::Block(expressions))
+        Ok(exprs)
     }
 
-    pub fn parse_args(&mut self) -> ParseResult<Vec<Identifier>> {
+    pub fn parse_args(&mut self) -> ParseResult<Vec<Param>> {
         let mut params = vec![];
         while !self.check(TokenType::RightParen)? && !self.check(TokenType::EOF)? {
-            params.push(self.parse_identifier()?);
+            let ident = self.parse_identifier()?;
+            let ty = self.parse_annotation()?;
+            params.push(Param::new(ident, ty));
 
             if !self.match_(TokenType::Comma)? {
                 break;
@@ -104,6 +315,22 @@ impl<'a> Parser<'a> {
         Ok(params)
     }
 
+    /// Parses an optional `: type` annotation, the type is left to the
+    /// checker to infer when there is none.
+    fn parse_annotation(&mut self) -> ParseResult<Type> {
+        if self.match_(TokenType::Colon)? {
+            self.parse_type()
+        } else {
+            Ok(Type::Unknown)
+        }
+    }
+
+    fn parse_type(&mut self) -> ParseResult<Type> {
+        let token = self.expect(TokenType::Identifier)?;
+        Type::from_name(token.source)
+            .ok_or_else(|| ParserError::UnknownType(token.source.to_string(), token.span))
+    }
+
     pub fn parse_expr_statement(&mut self) -> ParseResult<Expr> {
         let expr = self.expression()?;
         self.match_(TokenType::Semicolon)?;
@@ -117,7 +344,8 @@ impl<'a> Parser<'a> {
     fn block(&mut self) -> ParseResult<BlockDecl> {
         let mut exprs = vec![];
         while !self.check(TokenType::RightBrace)? && !self.check(TokenType::EOF)? {
-            exprs.push(self.declaration()?);
+            exprs.extend(self.declaration_or_synchronize());
+            self.skip_newlines()?;
         }
 
         self.expect(TokenType::RightBrace)?;
@@ -134,19 +362,23 @@ impl<'a> Parser<'a> {
             return Ok(self.consume()?);
         }
 
-        Err(ParserError::Expected(
-            expect.clone(),                         // TODO: Clone
-            self.peek_type()?.clone(),              // TODO: Clone
-            self.peek()?.position.line.clone(), // TODO: Clone
-        ))
+        let found = self.peek()?;
+        Err(ParserError::Expected(expect, found.token_type.clone(), found.span))
     }
 
     pub fn consume(&mut self) -> ParseResult<Token<'a>> {
-        self.tokens.pop().ok_or(ParserError::UnexpectedEOF)
+        let token = self.tokens.pop().ok_or(ParserError::UnexpectedEOF(self.eof))?;
+        self.previous = token.span;
+        Ok(token)
+    }
+
+    /// The span from `start` to the end of the last consumed token.
+    pub fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous)
     }
 
     pub fn peek(&self) -> ParseResult<&Token<'a>> {
-        self.tokens.last().ok_or(ParserError::UnexpectedEOF)
+        self.tokens.last().ok_or(ParserError::UnexpectedEOF(self.eof))
     }
 
     pub fn peek_type(&self) -> ParseResult<&TokenType> {
@@ -168,4 +400,194 @@ impl<'a> Parser<'a> {
     pub fn is_eof(&self) -> ParseResult<bool> {
         Ok(self.check(TokenType::EOF)?)
     }
+
+    pub fn skip_newlines(&mut self) -> ParseResult<()> {
+        while self.match_(TokenType::Newline)? {}
+        Ok(())
+    }
+
+    /// Matches a token that may be preceded by line breaks, e.g. an `else`
+    /// on the line after a closing brace. Leaves the line breaks alone when
+    /// the token doesn't match.
+    fn match_past_newlines(&mut self, token_type: TokenType) -> ParseResult<bool> {
+        let newlines = self.tokens.iter().rev()
+            .take_while(|t| t.token_type == TokenType::Newline)
+            .count();
+
+        if self.peek_nth_type(newlines) != Some(&token_type) {
+            return Ok(false);
+        }
+
+        self.skip_newlines()?;
+        self.match_(token_type)
+    }
+
+    fn peek_nth_type(&self, n: usize) -> Option<&TokenType> {
+        self.tokens.iter().rev().nth(n).map(|t| &t.token_type)
+    }
+
+    fn is_layout_fun(&self) -> bool {
+        let mut n = 1;
+        while self.peek_nth_type(n) == Some(&TokenType::Identifier) {
+            n += 1;
+        }
+        self.peek_nth_type(n) == Some(&TokenType::Colon)
+    }
+
+    /// Makes functions declared outside the tokens callable without
+    /// parentheses.
+    pub fn declare_functions(&mut self, functions: &[Identifier]) {
+        self.functions.extend(functions.iter().cloned());
+    }
+
+    /// Whether `ident` names a function declared anywhere in the program.
+    /// Functions can be called without parentheses, e.g. `greet`.
+    pub fn is_function(&self, ident: &str) -> bool {
+        self.functions.contains(ident)
+    }
+}
+
+/// Collects the names of all functions up front so calls can appear before
+/// the declaration they refer to.
+fn function_names(tokens: &[Token]) -> HashSet<Identifier> {
+    let mut names = HashSet::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.token_type != TokenType::Identifier {
+            continue;
+        }
+
+        let previous = if i == 0 { None } else { Some(&tokens[i - 1].token_type) };
+        let declared = match previous {
+            Some(TokenType::Fun) => true,
+            None
+            | Some(TokenType::Newline)
+            | Some(TokenType::Indent)
+            | Some(TokenType::Dedent)
+            | Some(TokenType::Semicolon)
+            | Some(TokenType::LeftBrace)
+            | Some(TokenType::RightBrace) => {
+                // Layout declaration: `name param param:`.
+                tokens[i + 1..].iter()
+                    .find(|t| t.token_type != TokenType::Identifier)
+                    .is_some_and(|t| t.token_type == TokenType::Colon)
+            }
+            _ => false,
+        };
+
+        if declared {
+            names.insert(token.source.to_string());
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::syntax::ast::{ExprKind, Program};
+    use crate::syntax::{lex, parse, Span};
+
+    fn parse_source(source: &str) -> (Program, Vec<String>) {
+        let (mut tokens, lex_errors) = lex(source);
+        assert!(lex_errors.is_empty());
+        let (program, errors) = parse(&mut tokens, &[]);
+        (program, errors.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn report_every_error() {
+        let (program, errors) = parse_source("let x = ;\nprint 1;\nlet = 2;\nprint 2;\n");
+
+        assert_eq!(errors, vec![
+            "[line 1] Error: unexpected ';'",
+            "[line 3] Error: expected identifier but found '='",
+        ]);
+        assert_eq!(program.len(), 2);
+        assert!(program.iter().all(|e| matches!(e.kind, ExprKind::Print { .. })));
+    }
+
+    #[test]
+    fn recover_inside_braces() {
+        let source = "fun f() {\n    let a = *;\n    print 1;\n}\nprint (;\nprint 2;\n";
+        let (program, errors) = parse_source(source);
+
+        assert_eq!(errors, vec![
+            "[line 2] Error: unexpected '*'",
+            "[line 5] Error: unexpected ';'",
+        ]);
+        assert_eq!(program.len(), 2);
+        match &program[0].kind {
+            ExprKind::Fun { body, .. } => assert_eq!(body.len(), 1),
+            kind => panic!("expected a function, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn recover_inside_layout_block() {
+        let source = "double x:\n    let = x\n    x * 2\nprint double 4\n";
+        let (program, errors) = parse_source(source);
+
+        assert_eq!(errors, vec!["[line 2] Error: expected identifier but found '='"]);
+        assert_eq!(program.len(), 2);
+        match &program[0].kind {
+            ExprKind::Fun { body, .. } => assert_eq!(body.len(), 1),
+            kind => panic!("expected a function, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn skip_block_of_broken_statement() {
+        // The body of the broken `if` is skipped without further errors.
+        let source = "if + {\n    print 1;\n}\nprint 2;\n";
+        let (program, errors) = parse_source(source);
+
+        assert_eq!(errors, vec!["[line 1] Error: unexpected '+'"]);
+        assert_eq!(program.len(), 1);
+    }
+
+    #[test]
+    fn require_initializer() {
+        let (program, errors) = parse_source("let b: bool;\nlet s: string;\nprint 1;\n");
+
+        assert_eq!(errors, vec![
+            "[line 1] Error: variable 'b' needs an initial value",
+            "[line 2] Error: variable 's' needs an initial value",
+        ]);
+        assert_eq!(program.len(), 1);
+    }
+
+    #[test]
+    fn span_groupings_from_their_parenthesis() {
+        let (program, errors) = parse_source("print (1)(2);\n");
+        assert!(errors.is_empty());
+
+        match &program[0].kind {
+            ExprKind::Print { value } => match &value.kind {
+                ExprKind::Call { callee, .. } => {
+                    assert_eq!(callee.span, Span::new(6, 9, 1, 7));
+                    assert_eq!(value.span, Span::new(6, 12, 1, 7));
+                }
+                kind => panic!("expected a call, found {:?}", kind),
+            },
+            kind => panic!("expected a print, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn recover_before_extern() {
+        let (program, errors) = parse_source("let a = * extern fun now() -> i32;\nprint now();\n");
+
+        assert_eq!(errors, vec!["[line 1] Error: unexpected '*'"]);
+        assert_eq!(program.len(), 2);
+        assert!(matches!(program[0].kind, ExprKind::Extern { .. }));
+    }
+
+    #[test]
+    fn skip_stray_closing_brace() {
+        let (program, errors) = parse_source("}\nprint 1;\n");
+
+        assert_eq!(errors, vec!["[line 1] Error: unexpected '}'"]);
+        assert_eq!(program.len(), 1);
+    }
 }
@@ -1,16 +1,18 @@
+use std::fmt;
+
 #[derive(Debug, PartialEq)]
 pub struct Token<'a> {
     pub token_type: TokenType,
     pub source: &'a str,
-    pub position: Position,
+    pub span: Span,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(token_type: TokenType, source: &'a str, position: Position) -> Self {
+    pub fn new(token_type: TokenType, source: &'a str, span: Span) -> Self {
         Token {
             token_type,
             source,
-            position,
+            span,
         }
     }
 }
@@ -25,11 +27,15 @@ pub enum TokenType {
     RightBracket,
     Comma,
     Dot,
+    DotDot,
     Minus,
     Plus,
     Star,
+    Percent,
     Slash,
     Semicolon,
+    Colon,
+    Arrow,
 
     Bang,
     BangEqual,
@@ -39,33 +45,120 @@ pub enum TokenType {
     LessThanEqual,
     GreaterThan,
     GreaterThanEqual,
+    And,
+    Or,
 
     String,
+    InterpolationStart,
+    InterpolationMid,
+    InterpolationEnd,
     Number,
     True,
     False,
 
     Let,
+    Extern,
     Print,
     If,
     Else,
     Fun,
+    Return,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
     Identifier,
 
+    Newline,
+    Indent,
+    Dedent,
+
+    /// Source the lexer couldn't make sense of, the error is reported by
+    /// the lexer.
+    Error,
     EOF,
 }
 
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TokenType::LeftParen => "'('",
+            TokenType::RightParen => "')'",
+            TokenType::LeftBrace => "'{'",
+            TokenType::RightBrace => "'}'",
+            TokenType::LeftBracket => "'['",
+            TokenType::RightBracket => "']'",
+            TokenType::Comma => "','",
+            TokenType::Dot => "'.'",
+            TokenType::DotDot => "'..'",
+            TokenType::Minus => "'-'",
+            TokenType::Plus => "'+'",
+            TokenType::Star => "'*'",
+            TokenType::Percent => "'%'",
+            TokenType::Slash => "'/'",
+            TokenType::Semicolon => "';'",
+            TokenType::Colon => "':'",
+            TokenType::Arrow => "'->'",
+            TokenType::Bang => "'!'",
+            TokenType::BangEqual => "'!='",
+            TokenType::Equal => "'='",
+            TokenType::EqualEqual => "'=='",
+            TokenType::LessThan => "'<'",
+            TokenType::LessThanEqual => "'<='",
+            TokenType::GreaterThan => "'>'",
+            TokenType::GreaterThanEqual => "'>='",
+            TokenType::And => "'and'",
+            TokenType::Or => "'or'",
+            TokenType::String => "string",
+            TokenType::InterpolationStart
+            | TokenType::InterpolationMid
+            | TokenType::InterpolationEnd => "interpolated string",
+            TokenType::Number => "number",
+            TokenType::True => "'true'",
+            TokenType::False => "'false'",
+            TokenType::Let => "'let'",
+            TokenType::Extern => "'extern'",
+            TokenType::Print => "'print'",
+            TokenType::If => "'if'",
+            TokenType::Else => "'else'",
+            TokenType::Fun => "'fun'",
+            TokenType::Return => "'return'",
+            TokenType::While => "'while'",
+            TokenType::For => "'for'",
+            TokenType::In => "'in'",
+            TokenType::Break => "'break'",
+            TokenType::Continue => "'continue'",
+            TokenType::Identifier => "identifier",
+            TokenType::Newline => "newline",
+            TokenType::Indent => "indent",
+            TokenType::Dedent => "dedent",
+            TokenType::Error => "invalid token",
+            TokenType::EOF => "end of file",
+        };
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Position {
+        write!(f, "{}", s)
+    }
+}
+
+/// A range of the source, `start` and `end` are byte offsets, `line` and
+/// `column` where it starts, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
     pub start: usize,
     pub end: usize,
     pub line: usize,
+    pub column: usize,
 }
 
-impl Position {
-    pub fn new(start: usize, end: usize, line: usize) -> Self {
-        Position { start, end, line }
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span { start, end, line, column }
+    }
+
+    /// The span from the start of this one to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end.max(self.end), ..self }
     }
 }
 
@@ -77,10 +170,19 @@ impl ToKeyword for &str {
     fn to_keyword(self) -> TokenType {
         match self {
             "fun" => TokenType::Fun,
+            "extern" => TokenType::Extern,
+            "return" => TokenType::Return,
+            "while" => TokenType::While,
+            "for" => TokenType::For,
+            "in" => TokenType::In,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
             "let" => TokenType::Let,
             "print" => TokenType::Print,
             "if" => TokenType::If,
             "else" => TokenType::Else,
+            "and" => TokenType::And,
+            "or" => TokenType::Or,
             "true" => TokenType::True,
             "false" => TokenType::False,
             _ => TokenType::Identifier,
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use crate::syntax::ast::*;
+use crate::syntax::Span;
+use crate::types::error::{Constraint, TypeError, TypeResult};
+
+struct Signature {
+    params: Vec<Type>,
+    ret: Type,
+}
+
+pub struct Checker {
+    /// The type each type variable has been unified with.
+    substitution: Vec<Option<Type>>,
+    scopes: Vec<HashMap<Identifier, Type>>,
+    functions: HashMap<Identifier, Signature>,
+    /// Constraints on type variables, checked once all types are inferred.
+    deferred: Vec<(Constraint, Type, Span)>,
+    /// The result type of the function being checked, `None` at the top
+    /// level.
+    ret: Option<Type>,
+    /// How many loops enclose the expression being checked.
+    loops: usize,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            substitution: vec![],
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            deferred: vec![],
+            ret: None,
+            loops: 0,
+        }
+    }
+
+    pub fn check_program(&mut self, program: &mut Program) -> TypeResult<()> {
+        self.declare_functions(program);
+
+        for expr in program.iter_mut() {
+            self.check_expr(expr)?;
+        }
+
+        for (constraint, ty, span) in std::mem::take(&mut self.deferred) {
+            let ty = self.finalize(ty);
+            if !constraint.is_satisfied_by(ty) {
+                return Err(TypeError::Unsatisfied(constraint, ty, span));
+            }
+        }
+
+        for expr in program.iter_mut() {
+            self.apply(expr);
+        }
+
+        Ok(())
+    }
+
+    /// Gives every function a signature up front so it can be called before
+    /// it is declared. Parameters and results are inferred from their use.
+    fn declare_functions(&mut self, exprs: &mut [Expr]) {
+        for expr in exprs {
+            match &mut expr.kind {
+                ExprKind::Fun { ident, params, ret, body } => {
+                    for p in params.iter_mut() {
+                        if p.ty == Type::Unknown {
+                            p.ty = self.fresh();
+                        }
+                    }
+                    if *ret == Type::Unknown {
+                        *ret = self.fresh();
+                    }
+
+                    let signature = Signature {
+                        params: params.iter().map(|p| p.ty).collect(),
+                        ret: *ret,
+                    };
+                    self.functions.insert(ident.clone(), signature);
+
+                    self.declare_functions(body);
+                }
+                ExprKind::Extern { ident, params, ret } => {
+                    let signature = Signature {
+                        params: params.iter().map(|p| p.ty).collect(),
+                        ret: *ret,
+                    };
+                    self.functions.insert(ident.clone(), signature);
+                }
+                ExprKind::Block(exprs) => self.declare_functions(exprs),
+                ExprKind::IfElse { then, else_, .. } => {
+                    self.declare_functions(std::slice::from_mut(then.as_mut()));
+                    if let Some(else_) = else_ {
+                        self.declare_functions(std::slice::from_mut(else_.as_mut()));
+                    }
+                }
+                ExprKind::While { body, .. } | ExprKind::For { body, .. } => {
+                    self.declare_functions(std::slice::from_mut(body.as_mut()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &mut Expr) -> TypeResult<Type> {
+        let span = expr.span;
+
+        let ty = match &mut expr.kind {
+            ExprKind::Block(exprs) => {
+                self.scopes.push(HashMap::new());
+                let result = self.check_statements(exprs);
+                self.scopes.pop();
+                result?;
+                Type::Unit
+            }
+            ExprKind::Binary { left, op, right } => {
+                let left = self.check_expr(left)?;
+                let right = self.check_expr(right)?;
+                self.check_binary(*op, left, right, span)?
+            }
+            ExprKind::Unary { op, expr } => {
+                let ty = self.check_expr(expr)?;
+                match op {
+                    UnaryOperator::Negate => {
+                        self.require(Constraint::Numeric, ty, span)?;
+                        ty
+                    }
+                    UnaryOperator::Not => self.unify(Type::Bool, ty, span)?,
+                }
+            }
+            ExprKind::LetAssign { ident, ty, initializer } => {
+                if *ty == Type::Unknown {
+                    *ty = self.check_expr(initializer)?;
+                    self.require(Constraint::Value, *ty, span)?;
+                } else {
+                    self.check_expected(initializer, *ty)?;
+                }
+                self.scopes.last_mut().unwrap().insert(ident.clone(), *ty);
+                Type::Unit
+            }
+            ExprKind::LetGet { ident } => self.lookup(ident, span)?,
+            ExprKind::LetSet { ident, expr } => {
+                let target = self.lookup(ident, span)?;
+                self.check_expected(expr, target)?;
+                Type::Unit
+            }
+            ExprKind::Print { value } => {
+                let ty = self.check_expr(value)?;
+                self.require(Constraint::Value, ty, span)?;
+                Type::Unit
+            }
+            ExprKind::IfElse { condition, then, else_ } => {
+                let ty = self.check_expr(condition)?;
+                self.unify(Type::Bool, ty, condition.span)?;
+
+                self.check_branch(then)?;
+                if let Some(else_) = else_ {
+                    self.check_branch(else_)?;
+                }
+                Type::Unit
+            }
+            ExprKind::Fun { ident, params, ret, body } => {
+                self.check_fun(ident, params, *ret, body, span)?;
+                Type::Unit
+            }
+            ExprKind::Call { callee, args } => {
+                let ident = match &callee.kind {
+                    ExprKind::LetGet { ident } => ident.clone(),
+                    _ => return Err(TypeError::NotCallable(span)),
+                };
+
+                let (params, ret) = match self.functions.get(&ident) {
+                    Some(signature) => (signature.params.clone(), signature.ret),
+                    None => return Err(TypeError::UndefinedFunction(ident, span)),
+                };
+
+                if params.len() != args.len() {
+                    return Err(TypeError::ArgumentCount(ident, params.len(), args.len(), span));
+                }
+
+                for (arg, param) in args.iter_mut().zip(params) {
+                    self.check_expected(arg, param)?;
+                }
+
+                ret
+            }
+            ExprKind::Return { value } => {
+                let ret = self.ret.ok_or(TypeError::ReturnOutsideFunction(span))?;
+                match value {
+                    Some(value) => self.check_expected(value, ret)?,
+                    None => self.coerce(Type::Unit, ret, span)?,
+                }
+                Type::Unit
+            }
+            ExprKind::While { condition, body } => {
+                let ty = self.check_expr(condition)?;
+                self.unify(Type::Bool, ty, condition.span)?;
+
+                self.check_loop_body(body, None)?;
+                Type::Unit
+            }
+            ExprKind::For { ident, start, end, body } => {
+                self.check_expected(start, Type::I32)?;
+                self.check_expected(end, Type::I32)?;
+
+                self.check_loop_body(body, Some(ident))?;
+                Type::Unit
+            }
+            ExprKind::Break if self.loops == 0 => return Err(TypeError::BreakOutsideLoop(span)),
+            ExprKind::Continue if self.loops == 0 => return Err(TypeError::ContinueOutsideLoop(span)),
+            ExprKind::Break | ExprKind::Continue | ExprKind::Extern { .. } => Type::Unit,
+            ExprKind::Interpolation(parts) => {
+                for part in parts.iter_mut() {
+                    let ty = self.check_expr(part)?;
+                    self.require(Constraint::Value, ty, part.span)?;
+                }
+                Type::String
+            }
+            ExprKind::Literal(literal) => match literal {
+                LiteralExpr::Number(n) if i32::try_from(*n).is_err() => Type::I64,
+                LiteralExpr::Number(_) => Type::I32,
+                LiteralExpr::Float(_) => Type::F64,
+                LiteralExpr::String(_) => Type::String,
+                LiteralExpr::True | LiteralExpr::False => Type::Bool,
+            },
+        };
+
+        expr.ty = ty;
+        Ok(ty)
+    }
+
+    /// Checks an expression whose value is used where `expected` is, number
+    /// literals take the expected type directly, e.g. `let x: f32 = 1.5;`.
+    fn check_expected(&mut self, expr: &mut Expr, expected: Type) -> TypeResult<()> {
+        let expected = self.prune(expected);
+        if let ExprKind::Literal(literal) = &expr.kind {
+            let fits = match literal {
+                LiteralExpr::Number(n) => expected.is_float()
+                    || expected == Type::I64
+                    || (expected == Type::I32 && i32::try_from(*n).is_ok()),
+                LiteralExpr::Float(_) => expected.is_float(),
+                _ => false,
+            };
+            if fits {
+                expr.ty = expected;
+                return Ok(());
+            }
+        }
+
+        let ty = self.check_expr(expr)?;
+        self.coerce(ty, expected, expr.span)
+    }
+
+    fn check_statements(&mut self, exprs: &mut [Expr]) -> TypeResult<Type> {
+        let mut last = Type::Unit;
+        for expr in exprs {
+            last = self.check_expr(expr)?;
+        }
+        Ok(last)
+    }
+
+    fn check_branch(&mut self, branch: &mut Expr) -> TypeResult<()> {
+        self.scopes.push(HashMap::new());
+        let result = self.check_expr(branch);
+        self.scopes.pop();
+        result.map(|_| ())
+    }
+
+    /// Checks the body of a loop, `counter` is the variable of a `for` loop.
+    fn check_loop_body(&mut self, body: &mut Expr, counter: Option<&Identifier>) -> TypeResult<()> {
+        let mut scope = HashMap::new();
+        if let Some(counter) = counter {
+            scope.insert(counter.clone(), Type::I32);
+        }
+
+        self.scopes.push(scope);
+        self.loops += 1;
+        let result = self.check_expr(body);
+        self.loops -= 1;
+        self.scopes.pop();
+        result.map(|_| ())
+    }
+
+    fn check_fun(&mut self, ident: &Identifier, params: &[Param], ret: Type, body: &mut BlockDecl, span: Span) -> TypeResult<()> {
+        // Functions see the globals and their own parameters.
+        let globals = self.scopes[0].clone();
+        let locals = params.iter().map(|p| (p.ident.clone(), p.ty)).collect();
+        let enclosing = std::mem::replace(&mut self.scopes, vec![globals, locals]);
+        let enclosing_ret = self.ret.replace(ret);
+        let enclosing_loops = std::mem::take(&mut self.loops);
+
+        let result = self.check_fun_body(ident, ret, body, span);
+
+        self.scopes = enclosing;
+        self.ret = enclosing_ret;
+        self.loops = enclosing_loops;
+        result
+    }
+
+    fn check_fun_body(&mut self, ident: &Identifier, ret: Type, body: &mut BlockDecl, span: Span) -> TypeResult<()> {
+        // The value of a trailing expression is the result.
+        if let Some((last, rest)) = body.split_last_mut() {
+            if last.is_value() {
+                self.check_statements(rest)?;
+                return self.check_expected(last, ret);
+            }
+        }
+
+        self.check_statements(body)?;
+        if body.iter().any(Expr::always_returns) {
+            return Ok(());
+        }
+
+        // Falling off the end returns nothing.
+        match self.prune(ret) {
+            Type::Unit => Ok(()),
+            Type::Var(_) => self.coerce(Type::Unit, ret, span),
+            _ => Err(TypeError::MissingReturn(ident.clone(), span)),
+        }
+    }
+
+    fn check_binary(&mut self, op: BinaryOperator, left: Type, right: Type, span: Span) -> TypeResult<Type> {
+        let (left, right) = (self.prune(left), self.prune(right));
+
+        if matches!(op, BinaryOperator::And | BinaryOperator::Or) {
+            self.unify(Type::Bool, left, span)
+                .and_then(|_| self.unify(Type::Bool, right, span))
+                .map_err(|_| TypeError::Operands(op, left, right, span))?;
+            return Ok(Type::Bool);
+        }
+
+        let operand = if left.is_numeric() && right.is_numeric() {
+            left.wider(right)
+        } else if matches!(left, Type::Var(_)) || matches!(right, Type::Var(_)) {
+            self.unify(left, right, span)?
+        } else if left == right {
+            left
+        } else {
+            return Err(TypeError::Operands(op, left, right, span));
+        };
+
+        let (constraint, result) = match op {
+            BinaryOperator::Add
+            | BinaryOperator::Subtract
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide => (Constraint::Numeric, operand),
+            BinaryOperator::Remainder => (Constraint::Integer, operand),
+            BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanEqual
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessThanEqual => (Constraint::Numeric, Type::Bool),
+            BinaryOperator::Equal | BinaryOperator::BangEqual => (Constraint::Equatable, Type::Bool),
+            BinaryOperator::And | BinaryOperator::Or => unreachable!("checked above"),
+        };
+
+        match operand {
+            Type::Var(_) => self.deferred.push((constraint, operand, span)),
+            _ if !constraint.is_satisfied_by(operand) => {
+                return Err(TypeError::Operands(op, left, right, span));
+            }
+            _ => {}
+        }
+
+        Ok(result)
+    }
+
+    fn lookup(&self, ident: &Identifier, span: Span) -> TypeResult<Type> {
+        self.scopes.iter().rev()
+            .find_map(|scope| scope.get(ident).copied())
+            .ok_or_else(|| TypeError::UndefinedVariable(ident.clone(), span))
+    }
+
+    fn require(&mut self, constraint: Constraint, ty: Type, span: Span) -> TypeResult<()> {
+        match self.prune(ty) {
+            Type::Var(_) => self.deferred.push((constraint, ty, span)),
+            ty if !constraint.is_satisfied_by(ty) => {
+                return Err(TypeError::Unsatisfied(constraint, ty, span));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Like `unify` but also accepts a value that widens to the expected type.
+    fn coerce(&mut self, found: Type, expected: Type, span: Span) -> TypeResult<()> {
+        let (found, expected) = (self.prune(found), self.prune(expected));
+        if found.is_numeric() && found.widens_to(expected) {
+            return Ok(());
+        }
+
+        self.unify(expected, found, span)?;
+        Ok(())
+    }
+
+    fn unify(&mut self, expected: Type, found: Type, span: Span) -> TypeResult<Type> {
+        let (expected, found) = (self.prune(expected), self.prune(found));
+        match (expected, found) {
+            _ if expected == found => Ok(expected),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                self.substitution[var] = Some(ty);
+                Ok(ty)
+            }
+            _ => Err(TypeError::Mismatch(expected, found, span)),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Var(self.substitution.len() - 1)
+    }
+
+    /// Follows the substitution until reaching a known type or a variable
+    /// that hasn't been unified yet.
+    fn prune(&self, ty: Type) -> Type {
+        match ty {
+            Type::Var(var) => match self.substitution[var] {
+                Some(ty) => self.prune(ty),
+                None => ty,
+            },
+            _ => ty,
+        }
+    }
+
+    /// Values that are never constrained default to `i32`.
+    fn finalize(&self, ty: Type) -> Type {
+        match self.prune(ty) {
+            Type::Var(_) => Type::I32,
+            ty => ty,
+        }
+    }
+
+    /// Replaces the type variables in `expr` with the types they resolved to.
+    fn apply(&self, expr: &mut Expr) {
+        expr.ty = self.finalize(expr.ty);
+
+        match &mut expr.kind {
+            ExprKind::Block(exprs) | ExprKind::Interpolation(exprs) => {
+                for e in exprs {
+                    self.apply(e);
+                }
+            }
+            ExprKind::Binary { left, right, .. } => {
+                self.apply(left);
+                self.apply(right);
+            }
+            ExprKind::Unary { expr, .. }
+            | ExprKind::LetSet { expr, .. }
+            | ExprKind::Print { value: expr } => self.apply(expr),
+            ExprKind::LetAssign { ty, initializer, .. } => {
+                *ty = self.finalize(*ty);
+                self.apply(initializer);
+            }
+            ExprKind::IfElse { condition, then, else_ } => {
+                self.apply(condition);
+                self.apply(then);
+                if let Some(else_) = else_ {
+                    self.apply(else_);
+                }
+            }
+            ExprKind::Fun { params, ret, body, .. } => {
+                for p in params.iter_mut() {
+                    p.ty = self.finalize(p.ty);
+                }
+                *ret = self.finalize(*ret);
+                for e in body {
+                    self.apply(e);
+                }
+            }
+            ExprKind::Call { args, .. } => {
+                for arg in args {
+                    self.apply(arg);
+                }
+            }
+            ExprKind::Return { value } => {
+                if let Some(value) = value {
+                    self.apply(value);
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.apply(condition);
+                self.apply(body);
+            }
+            ExprKind::For { start, end, body, .. } => {
+                self.apply(start);
+                self.apply(end);
+                self.apply(body);
+            }
+            ExprKind::LetGet { .. }
+            | ExprKind::Extern { .. }
+            | ExprKind::Break
+            | ExprKind::Continue
+            | ExprKind::Literal(_) => {}
+        }
+    }
+}
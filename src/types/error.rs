@@ -0,0 +1,99 @@
+use std::fmt;
+use crate::syntax::ast::{BinaryOperator, Identifier, Type};
+use crate::syntax::Span;
+
+pub type TypeResult<T> = Result<T, TypeError>;
+
+#[derive(Debug, PartialEq)]
+pub enum TypeError {
+    Mismatch(Type, Type, Span),
+    Operands(BinaryOperator, Type, Type, Span),
+    Unsatisfied(Constraint, Type, Span),
+    UndefinedVariable(Identifier, Span),
+    UndefinedFunction(Identifier, Span),
+    NotCallable(Span),
+    ArgumentCount(Identifier, usize, usize, Span),
+    ReturnOutsideFunction(Span),
+    MissingReturn(Identifier, Span),
+    BreakOutsideLoop(Span),
+    ContinueOutsideLoop(Span),
+}
+
+impl TypeError {
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::Mismatch(_, _, span)
+            | TypeError::Operands(_, _, _, span)
+            | TypeError::Unsatisfied(_, _, span)
+            | TypeError::UndefinedVariable(_, span)
+            | TypeError::UndefinedFunction(_, span)
+            | TypeError::NotCallable(span)
+            | TypeError::ArgumentCount(_, _, _, span)
+            | TypeError::ReturnOutsideFunction(span)
+            | TypeError::MissingReturn(_, span)
+            | TypeError::BreakOutsideLoop(span)
+            | TypeError::ContinueOutsideLoop(span) => *span,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            TypeError::Mismatch(expected, found, _) => format!("expected {} but found {}", expected, found),
+            TypeError::Operands(op, left, right, _) => {
+                format!("cannot apply '{}' to {} and {}", op, left, right)
+            }
+            TypeError::Unsatisfied(constraint, found, _) => format!("expected {} but found {}", constraint, found),
+            TypeError::UndefinedVariable(ident, _) => format!("undefined variable '{}'", ident),
+            TypeError::UndefinedFunction(ident, _) => format!("undefined function '{}'", ident),
+            TypeError::NotCallable(_) => "can only call functions".to_string(),
+            TypeError::ArgumentCount(ident, expected, found, _) => {
+                format!("'{}' expects {} arguments but got {}", ident, expected, found)
+            }
+            TypeError::ReturnOutsideFunction(_) => "can't return from top-level code".to_string(),
+            TypeError::MissingReturn(ident, _) => {
+                format!("'{}' can reach its end without returning a value", ident)
+            }
+            TypeError::BreakOutsideLoop(_) => "can't use 'break' outside of a loop".to_string(),
+            TypeError::ContinueOutsideLoop(_) => "can't use 'continue' outside of a loop".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.span().line, self.message())
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// A requirement on a type that is checked once the type is known.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Constraint {
+    Numeric,
+    Integer,
+    Equatable,
+    Value,
+}
+
+impl Constraint {
+    pub fn is_satisfied_by(self, ty: Type) -> bool {
+        match self {
+            Constraint::Numeric => ty.is_numeric(),
+            Constraint::Integer => ty.is_integer(),
+            Constraint::Equatable => ty.is_numeric() || ty == Type::Bool,
+            Constraint::Value => ty != Type::Unit,
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::Numeric => write!(f, "a number"),
+            Constraint::Integer => write!(f, "an integer"),
+            Constraint::Equatable => write!(f, "a number or bool"),
+            Constraint::Value => write!(f, "a value"),
+        }
+    }
+}
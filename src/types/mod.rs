@@ -0,0 +1,109 @@
+use crate::syntax::ast::Program;
+use crate::types::checker::Checker;
+use crate::types::error::TypeResult;
+
+mod checker;
+pub mod error;
+
+/// Infers the type of every expression in `program`, the returned program has
+/// no unknown types left.
+pub fn check(mut program: Program) -> TypeResult<Program> {
+    let mut checker = Checker::new();
+    checker.check_program(&mut program)?;
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::syntax::{lex, parse, Span};
+    use crate::syntax::ast::{BinaryOperator, Type};
+    use crate::types::check;
+    use crate::types::error::TypeError;
+
+    fn check_source(source: &str) -> Result<(), TypeError> {
+        let (mut tokens, lex_errors) = lex(source);
+        assert!(lex_errors.is_empty());
+        let (program, errors) = parse(&mut tokens, &[]);
+        assert!(errors.is_empty());
+        check(program).map(|_| ())
+    }
+
+    #[test]
+    fn infer_params_from_calls() {
+        assert_eq!(check_source("twice s:\n    \"$s$s\"\nprint twice \"a\""), Ok(()));
+    }
+
+    #[test]
+    fn reject_mismatched_operands() {
+        assert_eq!(
+            check_source("print 1;\nprint true + 1;"),
+            Err(TypeError::Operands(BinaryOperator::Add, Type::Bool, Type::I32, Span::new(15, 23, 2, 7)))
+        );
+    }
+
+    #[test]
+    fn reject_non_bool_logical_operands() {
+        assert_eq!(
+            check_source("print 1 and true;"),
+            Err(TypeError::Operands(BinaryOperator::And, Type::I32, Type::Bool, Span::new(6, 16, 1, 7)))
+        );
+    }
+
+    #[test]
+    fn reject_float_remainder() {
+        assert_eq!(
+            check_source("print 1.5 % 2;"),
+            Err(TypeError::Operands(BinaryOperator::Remainder, Type::F64, Type::I32, Span::new(6, 13, 1, 7)))
+        );
+    }
+
+    #[test]
+    fn reject_undefined_variable() {
+        assert_eq!(
+            check_source("let a = 1;\nprint b;"),
+            Err(TypeError::UndefinedVariable("b".to_string(), Span::new(17, 18, 2, 7)))
+        );
+    }
+
+    #[test]
+    fn reject_narrowing_annotation() {
+        assert_eq!(
+            check_source("let x: i32 = 1.5;"),
+            Err(TypeError::Mismatch(Type::I32, Type::F64, Span::new(13, 16, 1, 14)))
+        );
+    }
+
+    #[test]
+    fn reject_falling_off_value_function() {
+        assert_eq!(
+            check_source("fun half(n: i32) -> i32 {\n    if n > 0 { return n / 2; }\n}"),
+            Err(TypeError::MissingReturn("half".to_string(), Span::new(0, 58, 1, 1)))
+        );
+        assert_eq!(
+            check_source("sign n:\n    if n < 0:\n        return -1\n    print n"),
+            Err(TypeError::MissingReturn("sign".to_string(), Span::new(0, 51, 1, 1)))
+        );
+    }
+
+    #[test]
+    fn reject_top_level_return() {
+        assert_eq!(check_source("print 1;\nreturn 2;"), Err(TypeError::ReturnOutsideFunction(Span::new(9, 18, 2, 1))));
+    }
+
+    #[test]
+    fn reject_break_outside_loop() {
+        assert_eq!(check_source("while true { break; }\nbreak;"), Err(TypeError::BreakOutsideLoop(Span::new(22, 27, 2, 1))));
+        assert_eq!(
+            check_source("for i in 0..3 {\n    fun f() { continue; }\n}"),
+            Err(TypeError::ContinueOutsideLoop(Span::new(30, 38, 2, 15)))
+        );
+    }
+
+    #[test]
+    fn reject_non_bool_condition() {
+        assert_eq!(
+            check_source("if 1 { print 2; }"),
+            Err(TypeError::Mismatch(Type::Bool, Type::I32, Span::new(3, 4, 1, 4)))
+        );
+    }
+}
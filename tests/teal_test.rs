@@ -2,9 +2,8 @@
 mod tests {
     use regex::Regex;
     use std::fs;
-    use std::io::Cursor;
     use walkdir::WalkDir;
-    use teal::run_with_output;
+    use teal::{interpret_with, Buffer, Engine, TealError};
 
     #[derive(PartialEq, Debug)]
     enum TestResult {
@@ -47,27 +46,54 @@ mod tests {
         TestResult::Ok
     }
 
-    fn execute(source: &str) -> (Vec<String>, TestResult) {
-        match run_with_output(source) {
-            Ok(output) => {
-                (output, TestResult::Ok)
+    fn compile_and_run(source: &str, output: Buffer) -> anyhow::Result<()> {
+        Engine::new().compile(source)?.instantiate_with(output)?.run()?;
+        Ok(())
+    }
+
+    /// What the program printed followed by its runtime error, or the lines
+    /// of its compile errors.
+    fn execute(source: &str, run: fn(&str, Buffer) -> anyhow::Result<()>) -> (Vec<String>, TestResult) {
+        let output = Buffer::new();
+        match run(source, output.clone()) {
+            Ok(()) => {
+                (output.take(), TestResult::Ok)
             },
             Err(err) => {
-                println!("Runtime error: {:?}", err);
-                (vec![], TestResult::RuntimeError)
+                println!("{}", err);
+                match err.downcast_ref::<TealError>() {
+                    Some(e) if e.is_compile_error() => {
+                        (e.to_string().lines().map(str::to_owned).collect(), TestResult::CompileError)
+                    }
+                    _ => {
+                        let mut output = output.take();
+                        output.push(err.to_string());
+                        (output, TestResult::RuntimeError)
+                    }
+                }
             }
         }
     }
 
     fn harness(source: &str) {
-        let expects = parse_expects(source, Regex::new(r"// expect: ?(.*)").unwrap(), 1);
-
         let expected_result = extract_expects(source);
+        // Every compile error is reported, in the order they appear.
+        let expects = if expected_result == TestResult::CompileError {
+            parse_expects(source, Regex::new(r"// (\[line \d+\] Error.*)").unwrap(), 1)
+        } else {
+            // A runtime error comes after what was printed before it.
+            parse_expects(source, Regex::new(r"// expect(?: runtime error)?: ?(.*)").unwrap(), 1)
+        };
 
         println!("Test case: {}", source);
-        let (output, result) = execute(source);
+        let (output, result) = execute(source, compile_and_run);
         assert_eq!(expects, output);
         assert_eq!(expected_result, result);
+
+        // The interpreter is the reference for the generated code.
+        let (output, result) = execute(source, interpret_with);
+        assert_eq!(expects, output, "interpreter output differs");
+        assert_eq!(expected_result, result, "interpreter result differs");
     }
 
     fn run_test_file(path: String) {